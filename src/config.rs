@@ -11,15 +11,16 @@
 //! | Linux   | `$XDG_CONFIG_HOME/pup/config.toml` or `$HOME`/.config/pup/config.toml |
 //! | macOS   | `$HOME`/Library/Application Support/pup/config.toml                   |
 
-use crate::operation::token::fetch_token;
+use crate::{operation::token::fetch_token, CliApp};
 use dirs::config_dir;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     fs::{create_dir, read_to_string, OpenOptions},
     io::Write,
     os::unix::fs::OpenOptionsExt,
     path::PathBuf,
-    process::exit,
+    process::{exit, Command},
 };
 
 /// User configuration file template
@@ -27,6 +28,83 @@ const TEMPLETE: &str = r#"# Configuration file for pup
 github_user_name = "your_user_name"
 github_repo_name = "your_repo_name"
 mail = "your_mail_address"
+
+# Where the files are uploaded to, one of "github", "gitlab", "gitea" (or
+# "forgejo") and "s3", see the `[s3]` table at the end for "s3"
+# backend = "github"
+
+# The GitLab instance and the ID or path of the project for the "gitlab"
+# backend, the project defaults to "github_user_name/github_repo_name"
+# gitlab_url = "https://gitlab.com"
+# gitlab_project = "group/subgroup/project"
+
+# The Gitea or Forgejo instance for the "gitea" backend, the repo is
+# "github_user_name/github_repo_name" on it
+# gitea_url = "https://codeberg.org"
+
+# Base URLs of the API, the web pages and the raw files for GitHub Enterprise
+# Server, the web and raw ones are derived from the API one if unset
+# api_base_url = "https://ghe.corp/api/v3"
+# web_base_url = "https://ghe.corp"
+# raw_base_url = "https://ghe.corp/raw"
+
+# Destinations matched by any of these regular expressions won't be uploaded
+# skip_patterns = ['^https?://cdn\.example\.com/', '\{\{.*\}\}']
+
+# Hosts whose images are (or are not) rehosted with `--rehost`, subdomains
+# are included. An empty allowlist allows all hosts.
+# rehost_allowlist = []
+# rehost_denylist = ["example.com"]
+
+# Directory where `--download` stores the images, relative to the markdown file
+# assets_dir = "assets"
+
+# How the uploaded files are named in the repo, "original" keeps the file name,
+# "hash" names them after the hash of the contents so that they never collide
+# naming = "original"
+
+# Path of the uploaded files in the repo, overrides `naming`. Variables:
+# {yyyy} {mm} {dd} {name} {stem} {ext} {hash} {hash8} {doc_stem} {random}
+# path_template = "{yyyy}/{mm}/{stem}-{hash8}.{ext}"
+
+# Branch that the files are uploaded to, the default branch of the repo if
+# unset, can be overridden by `--branch`
+# branch = "assets"
+
+# Author and committer of the upload commits, default to `git config user.name`
+# and `git config user.email`
+# author = { name = "your_name", email = "your_mail_address" }
+# committer = { name = "your_name", email = "your_mail_address" }
+
+# Commit message of the uploads, variables: {file} {doc} {host}
+# commit_message = "upload {file}"
+
+# URL style of the uploaded files, one of "blob", "raw", "jsdelivr", "pages",
+# "permalink" and "template". "template" uses `url_template`, variables:
+# {owner} {repo} {branch} {path} {sha}
+# url_style = "blob"
+# url_template = "https://img.example.com/{path}"
+
+# Upload all the files of a run in a single commit, through the Git Data API
+# single_commit = false
+
+# Extensions of the local files linked by regular links that are uploaded with
+# `--attachments`, and whether to rewrite the links to videos to `<video>` tags
+# attachment_extensions = ["pdf", "zip", "mp4"]
+# video_tag = false
+
+# Settings of the "s3" backend, the TOKEN is the secret access key. The
+# endpoint defaults to AWS, i.e., "https://s3.{region}.amazonaws.com", and the
+# URLs to "{endpoint}/{bucket}/{key}", variables of `url_template`:
+# {endpoint} {bucket} {key} {region}
+# [s3]
+# endpoint = "http://localhost:9000"
+# region = "us-east-1"
+# bucket = "images"
+# prefix = "blog"
+# access_key_id = "your_access_key_id"
+# public_read = false
+# url_template = "https://img.example.com/{key}"
 "#;
 
 /// User configuration
@@ -39,6 +117,70 @@ pub struct UserConfig {
     pub github_user_name: String,
     pub github_repo_name: String,
     pub mail: String,
+    /// Storage backend that the files are uploaded to.
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// Base URL of the GitLab instance, used by [`BackendKind::GitLab`].
+    #[serde(default = "default_gitlab_url")]
+    pub gitlab_url: String,
+    /// ID or path of the GitLab project, see [`UserConfig::gitlab_project`].
+    pub gitlab_project: Option<String>,
+    /// Base URL of the Gitea or Forgejo instance, used by
+    /// [`BackendKind::Gitea`].
+    #[serde(default = "default_gitea_url")]
+    pub gitea_url: String,
+    /// Settings of [`BackendKind::S3`].
+    pub s3: Option<S3Config>,
+    /// Base URL of the GitHub API, e.g., `https://ghe.corp/api/v3` for GitHub
+    /// Enterprise Server.
+    #[serde(default = "default_api_base_url")]
+    pub api_base_url: String,
+    /// Base URL of the GitHub web pages, see [`UserConfig::web_base_url`].
+    pub web_base_url: Option<String>,
+    /// Base URL of the raw files, see [`UserConfig::raw_base_url`].
+    pub raw_base_url: Option<String>,
+    /// Image destinations that match any of these patterns are skipped.
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub skip_patterns: Vec<Regex>,
+    /// Hosts whose images can be rehosted, empty means all hosts.
+    #[serde(default)]
+    pub rehost_allowlist: Vec<String>,
+    /// Hosts whose images should never be rehosted.
+    #[serde(default)]
+    pub rehost_denylist: Vec<String>,
+    /// Directory where the downloaded images are stored, relative to the
+    /// markdown file.
+    #[serde(default = "default_assets_dir")]
+    pub assets_dir: PathBuf,
+    /// How the uploaded files are named in the repo.
+    #[serde(default)]
+    pub naming: Naming,
+    /// Path of the uploaded files in the repo, overrides `naming`.
+    pub path_template: Option<String>,
+    /// Branch that the files are uploaded to, the default branch if `None`.
+    pub branch: Option<String>,
+    /// Author of the upload commits, see [`UserConfig::load`] for the default.
+    pub author: Option<Identity>,
+    /// Committer of the upload commits, see [`UserConfig::load`] for the
+    /// default.
+    pub committer: Option<Identity>,
+    /// URL style of the uploaded files.
+    #[serde(default)]
+    pub url_style: UrlStyle,
+    /// URL template used by [`UrlStyle::Template`].
+    pub url_template: Option<String>,
+    /// Upload all the files of a run in a single commit.
+    #[serde(default)]
+    pub single_commit: bool,
+    /// Commit message template of the uploads.
+    #[serde(default = "default_commit_message")]
+    pub commit_message: String,
+    /// Extensions of the linked local files to upload as attachments.
+    #[serde(default = "default_attachment_extensions")]
+    pub attachment_extensions: Vec<String>,
+    /// Rewrite the links to the uploaded videos to `<video>` tags.
+    #[serde(default)]
+    pub video_tag: bool,
     // This field is `Option`al since it will NOT be present in the
     // configuration file, to make `serde` successfully parse it without any
     // error, will make it `Option`al here.
@@ -48,15 +190,163 @@ pub struct UserConfig {
     pub token: Option<String>,
 }
 
+/// Storage backend that the files are uploaded to, see `util::backend`
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// A GitHub repo, through the GitHub API
+    #[default]
+    GitHub,
+    /// A GitLab project, through the repository files API
+    GitLab,
+    /// A repo on a Gitea or Forgejo instance, through the contents API
+    #[serde(alias = "forgejo")]
+    Gitea,
+    /// A bucket of Amazon S3 or an S3-compatible object storage
+    S3,
+}
+
+/// Settings of the S3 backend, the `[s3]` table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct S3Config {
+    /// Endpoint, see [`S3Config::endpoint`].
+    pub endpoint: Option<String>,
+    /// Region
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Bucket
+    pub bucket: String,
+    /// Prefix of the object keys
+    #[serde(default)]
+    pub prefix: String,
+    /// Access key ID, the TOKEN is the secret access key.
+    pub access_key_id: String,
+    /// Make the uploaded objects publicly readable with the `public-read` ACL.
+    #[serde(default)]
+    pub public_read: bool,
+    /// Template of the URLs of the uploaded objects, `{endpoint}/{bucket}/{key}`
+    /// if `None`.
+    pub url_template: Option<String>,
+}
+
+impl S3Config {
+    /// Endpoint without the trailing `/`, `endpoint` if it is set, the AWS one
+    /// of the region otherwise.
+    pub fn endpoint(&self) -> String {
+        match self.endpoint {
+            Some(ref endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://s3.{}.amazonaws.com", self.region),
+        }
+    }
+}
+
+/// How the uploaded files are named in the repo
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Naming {
+    /// Keep the original file name.
+    #[default]
+    Original,
+    /// Name the file after the hash of its contents, so that the same contents
+    /// are always uploaded to the same path.
+    Hash,
+}
+
+/// URL style of the uploaded files, see `util::file_url::file_url`
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlStyle {
+    /// The GitHub blob page
+    #[default]
+    Blob,
+    /// `raw.githubusercontent.com`
+    Raw,
+    /// The jsDelivr CDN
+    Jsdelivr,
+    /// GitHub Pages
+    Pages,
+    /// `raw.githubusercontent.com` pinned to the commit SHA
+    Permalink,
+    /// `UserConfig.url_template`
+    Template,
+}
+
+/// Author or committer of a commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// Name
+    pub name: String,
+    /// Email address
+    pub email: String,
+}
+
+impl Identity {
+    /// The identity configured in git, i.e., `git config user.name` and
+    /// `git config user.email`.
+    fn from_git() -> Option<Self> {
+        let git_config = |key: &str| {
+            let output = Command::new("git").args(["config", key]).output().ok()?;
+            let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+            (output.status.success() && !value.is_empty()).then_some(value)
+        };
+
+        Some(Identity {
+            name: git_config("user.name")?,
+            email: git_config("user.email")?,
+        })
+    }
+}
+
+/// Configuration fields overridden by the command line options
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigOverrides {
+    /// Overrides `UserConfig.branch`
+    pub branch: Option<String>,
+}
+
+impl From<&CliApp> for ConfigOverrides {
+    fn from(value: &CliApp) -> Self {
+        ConfigOverrides {
+            branch: value.branch.clone(),
+        }
+    }
+}
+
 impl UserConfig {
-    /// Try to construct an `UserConfig`.
-    pub fn load() -> Self {
+    /// Try to construct an `UserConfig`, the fields set in `overrides` take
+    /// precedence over the configuration file.
+    ///
+    /// `author` and `committer` default to the identity configured in git, or
+    /// `github_user_name` and `mail` if git has none.
+    pub fn load(overrides: &ConfigOverrides) -> Self {
         let config_path = config_file_path();
         let config_file_contents =
             read_to_string(config_path.as_path()).expect("pup: can not read config file");
 
         match toml::from_str::<UserConfig>(&config_file_contents) {
             Ok(mut config) => {
+                if let Some(ref branch) = overrides.branch {
+                    config.branch = Some(branch.clone());
+                }
+                let identity = Identity::from_git().unwrap_or_else(|| Identity {
+                    name: config.github_user_name.clone(),
+                    email: config.mail.clone(),
+                });
+                config.committer.get_or_insert_with(|| identity.clone());
+                config.author.get_or_insert(identity);
+                if config.backend == BackendKind::S3 && config.s3.is_none() {
+                    eprintln!("pup: `backend = \"s3\"` requires the `[s3]` table");
+                    exit(1);
+                }
+                if config.backend == BackendKind::GitHub
+                    && matches!(config.url_style, UrlStyle::Jsdelivr | UrlStyle::Pages)
+                    && !config.is_github_com()
+                {
+                    eprintln!(
+                        "pup: `url_style = \"jsdelivr\"` and `\"pages\"` only work with github.com"
+                    );
+                    exit(1);
+                }
                 config.token = Some(fetch_token());
                 config
             }
@@ -69,6 +359,114 @@ impl UserConfig {
             }
         }
     }
+
+    /// Base URL of the GitHub web pages, without the trailing `/`.
+    ///
+    /// `web_base_url` if it is set, otherwise it is derived from
+    /// `api_base_url`, i.e., `https://github.com` for `https://api.github.com`,
+    /// and `https://ghe.corp` for `https://ghe.corp/api/v3`.
+    pub fn web_base_url(&self) -> String {
+        if let Some(ref url) = self.web_base_url {
+            return url.trim_end_matches('/').to_string();
+        }
+
+        if self.is_github_com() {
+            return "https://github.com".to_string();
+        }
+        let api_base_url = self.api_base_url.trim_end_matches('/');
+        api_base_url
+            .strip_suffix("/api/v3")
+            .unwrap_or(api_base_url)
+            .to_string()
+    }
+
+    /// Base URL of the raw files, without the trailing `/`.
+    ///
+    /// `raw_base_url` if it is set, otherwise it is derived from
+    /// `api_base_url`, i.e., `https://raw.githubusercontent.com` for
+    /// `https://api.github.com`, and `https://ghe.corp/raw` for
+    /// `https://ghe.corp/api/v3`.
+    pub fn raw_base_url(&self) -> String {
+        if let Some(ref url) = self.raw_base_url {
+            return url.trim_end_matches('/').to_string();
+        }
+
+        if self.is_github_com() {
+            return "https://raw.githubusercontent.com".to_string();
+        }
+        format!("{}/raw", self.web_base_url())
+    }
+
+    /// Return true if the Repo is on github.com rather than a GitHub
+    /// Enterprise Server, i.e., `api_base_url` is the default one.
+    pub fn is_github_com(&self) -> bool {
+        self.api_base_url.trim_end_matches('/') == default_api_base_url()
+    }
+
+    /// ID or path of the GitLab project, `gitlab_project` if it is set,
+    /// `{github_user_name}/{github_repo_name}` otherwise.
+    pub fn gitlab_project(&self) -> String {
+        match self.gitlab_project {
+            Some(ref project) => project.trim_matches('/').to_string(),
+            None => format!("{}/{}", self.github_user_name, self.github_repo_name),
+        }
+    }
+}
+
+/// Configuration of the Repo `user/repo` with the `extra` fields appended, for
+/// the tests.
+#[cfg(test)]
+pub fn test_config(extra: &str) -> UserConfig {
+    let config = format!(
+        "github_user_name = \"user\"\ngithub_repo_name = \"repo\"\nmail = \"mail\"\n{}",
+        extra
+    );
+    toml::from_str(&config).unwrap()
+}
+
+/// Default value of `UserConfig.api_base_url`
+fn default_api_base_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+/// Default value of `UserConfig.gitlab_url`
+fn default_gitlab_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+/// Default value of `UserConfig.gitea_url`
+fn default_gitea_url() -> String {
+    "https://codeberg.org".to_string()
+}
+
+/// Default value of `S3Config.region`
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Default value of `UserConfig.assets_dir`
+fn default_assets_dir() -> PathBuf {
+    PathBuf::from("assets")
+}
+
+/// Default value of `UserConfig.commit_message`
+fn default_commit_message() -> String {
+    "upload {file}".to_string()
+}
+
+/// Default value of `UserConfig.attachment_extensions`
+fn default_attachment_extensions() -> Vec<String> {
+    ["pdf", "zip", "mp4"].map(String::from).to_vec()
+}
+
+/// Deserialize a list of regular expressions.
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
 }
 
 /// Return config directory path
@@ -12,7 +12,10 @@ mod config;
 mod operation;
 mod util;
 
-use crate::{config::init_config, operation::Operation};
+use crate::{
+    config::{init_config, ConfigOverrides},
+    operation::Operation,
+};
 use anyhow::Result;
 use clap::Parser;
 use std::path::PathBuf;
@@ -21,9 +24,41 @@ use std::path::PathBuf;
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct CliApp {
-    /// The target markdown or image file.
-    #[arg(exclusive = true)]
-    pub filepath: Option<PathBuf>,
+    /// The target markdown or image files, directories of markdown files, or
+    /// URLs of remote images to rehost. `-` reads a markdown document from
+    /// stdin and writes the result to stdout.
+    #[arg(value_name = "FILEPATH")]
+    pub filepaths: Vec<PathBuf>,
+    /// The directory that relative image paths are relative to when reading
+    /// from stdin, defaults to the current directory.
+    #[arg(long, value_name = "DIR", requires = "filepaths")]
+    pub base_dir: Option<PathBuf>,
+    /// Only process the markdown files matched by this glob in the directories.
+    #[arg(long, value_name = "GLOB", requires = "filepaths")]
+    pub include: Vec<String>,
+    /// Skip the markdown files matched by this glob in the directories.
+    #[arg(long, value_name = "GLOB", requires = "filepaths")]
+    pub exclude: Vec<String>,
+    /// Upload the images embedded as `data:` URIs in the markdown file.
+    #[arg(long, requires = "filepaths")]
+    pub data_uri: bool,
+    /// Rehost the remote images of the markdown file.
+    #[arg(long, requires = "filepaths")]
+    pub rehost: bool,
+    /// Also upload the local files linked from the markdown file, e.g., PDFs.
+    #[arg(long, requires = "filepaths")]
+    pub attachments: bool,
+    /// Upload to this branch instead of the one in the configuration file.
+    #[arg(
+        long,
+        value_name = "BRANCH",
+        requires = "filepaths",
+        conflicts_with = "download"
+    )]
+    pub branch: Option<String>,
+    /// Download the remote images of the markdown file to the assets directory.
+    #[arg(long, requires = "filepaths", conflicts_with_all = ["data_uri", "rehost", "attachments"])]
+    pub download: bool,
     /// Set the token.
     #[arg(long, exclusive = true)]
     pub set_token: bool,
@@ -39,7 +74,7 @@ fn main() -> Result<()> {
     init_config();
     let app = CliApp::parse();
     let op = Operation::try_from(&app)?;
-    op.execute()?;
+    op.execute(&ConfigOverrides::from(&app))?;
 
     Ok(())
 }
@@ -0,0 +1,148 @@
+//! The reverse of the markdown file manipulation.
+//!
+//! Download the remote images of a markdown file into the assets directory
+//! next to it, and replace the URLs with the relative paths.
+
+use crate::{
+    config::UserConfig,
+    operation::TargetFile,
+    util::{
+        dest_type::{dest_type, remote_url, DestType},
+        r#match::{find_images, replace, MatchedImage},
+        request::{Downloader, RemoteImage},
+        result::MdManipulationResult,
+    },
+};
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{canonicalize, create_dir_all, read, read_to_string, write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Download the remote images of the markdown file
+///
+/// The images are saved into `config.assets_dir` of the directory of the
+/// markdown file, the paths written into it are relative to that directory.
+pub fn md_download(
+    target_file: &TargetFile,
+    config: &UserConfig,
+    res: Arc<Mutex<MdManipulationResult>>,
+) {
+    let base_dir = canonicalize(target_file.file_path.as_path())
+        .expect("Failed to get absolute path of target markdown file")
+        .parent()
+        .expect("The target Markdown doc should have a parent directory")
+        .to_path_buf();
+    let assets_dir = base_dir.join(&config.assets_dir);
+    let doc = read_to_string(target_file.file_path.as_path()).expect("can not read target file");
+    let downloader = Downloader::init();
+
+    // the same image can be referenced multiple times, download it only once
+    // and replace all its occurrences.
+    let mut occurrences: HashMap<Url, Vec<MatchedImage>> = HashMap::new();
+    for mth in find_images(&doc) {
+        if dest_type(&mth.dest, &config.skip_patterns) == DestType::Remote {
+            if let Some(url) = remote_url(&mth.dest) {
+                occurrences.entry(url).or_default().push(mth);
+            }
+        }
+    }
+
+    let downloaded = occurrences
+        .into_par_iter()
+        .map(|(url, mths)| (downloader.download(&url), url, mths))
+        .collect::<Vec<_>>();
+
+    // save the images one by one so that their file names won't collide
+    let mut res = res.lock().unwrap();
+    let mut replacements = Vec::new();
+    for (image, url, mths) in downloaded {
+        let path: Result<String, Box<dyn Error>> = image
+            .and_then(|image| save(assets_dir.as_path(), image))
+            .and_then(|path| relative_path(&path, base_dir.as_path()))
+            .map_err(Into::into);
+        for mth in mths {
+            if let Some(path) = res.res_handling(&path, Path::new(url.as_str())) {
+                replacements.push((mth.range.clone(), mth.escape(&path)));
+            }
+        }
+    }
+
+    // write to the target markdown file
+    write(target_file.file_path.as_path(), replace(&doc, replacements))
+        .expect("can not write to the target markdown file");
+}
+
+/// Save `image` into `assets_dir`, return the path of the saved file.
+///
+/// If a different file with the same name exists, the hash of the image is
+/// appended to the file name.
+fn save(assets_dir: &Path, image: RemoteImage) -> Result<PathBuf> {
+    create_dir_all(assets_dir)?;
+
+    let mut path = assets_dir.join(&image.file_name);
+    if path.exists() && read(&path)? != image.contents {
+        let file_name = Path::new(&image.file_name);
+        let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+        let hash = Sha256::digest(&image.contents);
+        path = match file_name.extension() {
+            Some(ext) => {
+                assets_dir.join(format!("{}-{:.8x}.{}", stem, hash, ext.to_string_lossy()))
+            }
+            None => assets_dir.join(format!("{}-{:.8x}", stem, hash)),
+        };
+    }
+
+    if !path.exists() {
+        write(&path, &image.contents)?;
+    }
+
+    Ok(path)
+}
+
+/// Return `path` relative to `base_dir`, `path` itself if it is not under
+/// `base_dir`.
+fn relative_path(path: &Path, base_dir: &Path) -> Result<String> {
+    let relative = path.strip_prefix(base_dir).unwrap_or(path);
+    relative
+        .to_str()
+        .map(|relative| relative.to_string())
+        .ok_or_else(|| anyhow!("non-UTF-8 path {:?}", relative))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn test_save() {
+        let dir = TempDir::new("download");
+        let base_dir = dir.path();
+        let assets_dir = base_dir.join("assets");
+        let image = |contents: &[u8]| RemoteImage {
+            file_name: "a.png".to_string(),
+            contents: contents.to_vec(),
+        };
+
+        let first = save(&assets_dir, image(b"a")).unwrap();
+        assert_eq!(relative_path(&first, base_dir).unwrap(), "assets/a.png");
+        assert_eq!(save(&assets_dir, image(b"a")).unwrap(), first);
+
+        let second = save(&assets_dir, image(b"b")).unwrap();
+        assert_ne!(second, first);
+        assert!(relative_path(&second, base_dir)
+            .unwrap()
+            .starts_with("assets/a-"));
+        assert_eq!(
+            relative_path(Path::new("/elsewhere/a.png"), base_dir).unwrap(),
+            "/elsewhere/a.png"
+        );
+    }
+}
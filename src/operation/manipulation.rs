@@ -1,88 +1,353 @@
 //! Process target file.
 //!
-//! For a Markdown file, check line by line if there is a markdown
-//! image link, send the request and replace the path
+//! For Markdown files, parse them and find all the markdown image links, send
+//! the requests and replace the paths. An image shared by multiple files is
+//! uploaded only once. Images embedded as `data:` URIs can be uploaded as well
+//! if [`MdOptions::data_uri`] is set, and remote images can be rehosted if
+//! [`MdOptions::rehost`] is set. The files embedded by Obsidian wiki embeds
+//! are looked up in the vault. Local files linked by regular links, e.g.,
+//! PDFs, can be uploaded as attachments if [`MdOptions::attachments`] is set.
+//! With `UserConfig.single_commit`, all the files are uploaded in one commit.
 //!
-//! For a image, upload it the GitHub repo and place the returned URL into the
-//! system clipboard.
+//! A markdown document can also be read from stdin, and the manipulated one is
+//! written to stdout.
+//!
+//! The files are uploaded to the backend selected by `UserConfig.backend`.
+//!
+//! For images, upload them to the backend and place the returned URLs into the
+//! system clipboard. Remote image URLs are handled in the same way after
+//! downloading them.
 
 use crate::{
     config::UserConfig,
-    operation::TargetFile,
+    operation::{MdOptions, TargetFile},
     util::{
-        r#match::MatchedLine, request::Uploader, response::get_url, result::MdManipulationResult,
+        backend::{self, Backend, NewFile},
+        data_uri::DataUri,
+        dest_type::{dest_type, host_matches, remote_url, DestType},
+        file_url::is_own_url,
+        r#match::{find_all, replace, DestinationKind, MatchedImage},
+        request::Downloader,
+        result::MdManipulationResult,
+        vault::Vault,
     },
 };
+use anyhow::anyhow;
 use arboard::Clipboard;
 use colored::Colorize;
-use rayon::prelude::*;
+use rayon::{iter::Either, prelude::*};
+use reqwest::Url;
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Write},
-    ops::Index,
-    path::Path,
+    collections::HashMap,
+    error::Error,
+    fs::{canonicalize, read, read_to_string, write},
+    io::{self, stdin, stdout, Read, Write},
+    path::{Path, PathBuf},
+    slice,
     sync::{Arc, Mutex},
 };
 
+/// Image to be uploaded.
+///
+/// The same image can be referenced multiple times, this is used to upload
+/// it only once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Image {
+    /// Local image file
+    Local(PathBuf),
+    /// Image embedded as a `data:` URI
+    Data(String),
+    /// Remote image to be rehosted
+    Remote(Url),
+}
+
+impl Image {
+    /// Path used to report the result of this image.
+    fn display_path(&self) -> PathBuf {
+        match self {
+            Image::Local(path) => path.clone(),
+            Image::Data(uri) => {
+                let head = uri.chars().take(32).collect::<String>();
+                PathBuf::from(format!("{}...", head))
+            }
+            Image::Remote(url) => PathBuf::from(url.as_str()),
+        }
+    }
+}
+
+/// Extensions of the attachments that can be played in a `<video>` tag.
+const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "webm", "mov", "m4v", "ogv"];
+
+/// Return true if the extension of `path` is one of `extensions`.
+fn has_extension(path: &Path, extensions: &[impl AsRef<str>]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|allowed| allowed.as_ref().eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Occurrences of an image, i.e., (index of the document, matched image)
+type Occurrences = Vec<(usize, MatchedImage)>;
+
+/// URL of an uploaded image, or why it can not be uploaded
+type Uploaded = Result<String, Box<dyn Error>>;
+
+/// A markdown document to be manipulated
+#[derive(Debug)]
+struct Document {
+    /// Directory that the relative paths in the document are relative to
+    base_dir: PathBuf,
+    /// Path of the document, if it is a file
+    path: Option<PathBuf>,
+    /// Document contents
+    contents: String,
+}
+
 /// Call the functions from other modules to complete the task
+///
+/// The images of all the `target_files` are uploaded together, an image
+/// referenced by multiple files is uploaded only once.
 pub fn md_manipulate(
-    target_file: &TargetFile,
+    target_files: &[TargetFile],
     config: &UserConfig,
+    options: MdOptions,
     res: Arc<Mutex<MdManipulationResult>>,
 ) {
-    // buffer-wrapped target file
-    let md_file = BufReader::new(
-        File::open(target_file.file_path.as_path()).expect("can not open target file"),
-    );
-    let mut lines = md_file
-        .lines()
-        .map(|item| item.unwrap())
-        .collect::<Vec<String>>();
-    let uploader = Uploader::init(config.token.as_ref().unwrap());
-
-    lines.par_iter_mut().for_each(|line| {
-        line.push('\n');
-
-        if let Some(mut mth) = MatchedLine::new(line) {
-            // to escape simultaneous occurrence of mutable and immutable borrowing
-            let image_path = Path::new(mth.line.index(mth.range.clone())).to_path_buf();
-            res.lock().unwrap().res_handling(
-                manipulate_mthed_line(&uploader, &mut mth, config),
-                image_path.as_path(),
-            );
+    // a file that can not be read is reported and skipped
+    let docs = target_files
+        .iter()
+        .filter_map(|target_file| {
+            let path = target_file.file_path.as_path();
+            match read_document(path) {
+                Ok(doc) => Some(doc),
+                Err(msg) => {
+                    let failed: Uploaded = Err(msg.into());
+                    res.lock().unwrap().res_handling(&failed, path);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // write to the target markdown files
+    for (doc, new_doc) in docs
+        .iter()
+        .zip(docs_manipulate(&docs, config, options, res))
+    {
+        if let (Some(path), Some(new_doc)) = (&doc.path, new_doc) {
+            write(path, new_doc).expect("can not write to the target markdown file");
         }
-    });
-
-    // write to the target markdown file
-    let mut md_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(target_file.file_path.as_path())
-        .expect("can not open target markdown file for writing purpose");
-
-    lines.iter().for_each(|line| {
-        let _ = md_file
-            .write(line.as_bytes())
-            .expect("can not write to the target markdown file");
-    });
-
-    // print the statistical result
-    println!("{}", res.lock().unwrap());
+    }
+}
+
+/// Read the markdown file at `path`, the relative paths in it are relative to
+/// its directory.
+fn read_document(path: &Path) -> io::Result<Document> {
+    let base_dir = canonicalize(path)?
+        .parent()
+        .expect("The target Markdown doc should have a parent directory")
+        .to_path_buf();
+    Ok(Document {
+        base_dir,
+        path: Some(path.to_path_buf()),
+        contents: read_to_string(path)?,
+    })
+}
+
+/// Read a markdown document from stdin and write the manipulated one to
+/// stdout, the relative paths in it are relative to `base_dir`.
+pub fn md_filter(
+    base_dir: &Path,
+    config: &UserConfig,
+    options: MdOptions,
+    res: Arc<Mutex<MdManipulationResult>>,
+) {
+    let mut contents = String::new();
+    stdin()
+        .read_to_string(&mut contents)
+        .expect("can not read from stdin");
+    let base_dir =
+        canonicalize(base_dir).expect("Failed to get absolute path of the base directory");
+    let doc = Document {
+        base_dir,
+        path: None,
+        contents,
+    };
+
+    let new_doc = docs_manipulate(slice::from_ref(&doc), config, options, res)
+        .pop()
+        .flatten();
+    stdout()
+        .write_all(new_doc.as_ref().unwrap_or(&doc.contents).as_bytes())
+        .expect("can not write to stdout");
 }
 
-/// Deal with every matched line.
+/// Upload the images of `docs`, return the manipulated documents, `None` if a
+/// document is not changed.
 ///
-/// This is the helper function used inside of [`md_manipulate`].
-fn manipulate_mthed_line<'a>(
-    uploader: &Uploader,
-    mth: &'a mut MatchedLine<'a>,
+/// This is the helper function used inside of [`md_manipulate`] and
+/// [`md_filter`].
+fn docs_manipulate(
+    docs: &[Document],
     config: &UserConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let image_path = mth.line.index(mth.range.clone());
-    let response = uploader.upload(image_path, config)?;
-    let url = get_url(response)?;
-    mth.replace(url.as_str());
-    Ok(())
+    options: MdOptions,
+    res: Arc<Mutex<MdManipulationResult>>,
+) -> Vec<Option<String>> {
+    let backend = backend::init(config);
+    let downloader = Downloader::init();
+
+    // the same image can be referenced multiple times, upload it only once and
+    // replace all its occurrences.
+    let mut occurrences: HashMap<Image, Occurrences> = HashMap::new();
+    for (idx, doc) in docs.iter().enumerate() {
+        let base_dir = doc.base_dir.as_path();
+        let vault = Vault::discover(base_dir);
+
+        for mth in find_all(&doc.contents, vault.is_some()) {
+            // only the local files are uploaded as attachments
+            let dest = dest_type(&mth.dest, &config.skip_patterns);
+            if mth.link.is_some()
+                && !(options.attachments
+                    && dest == DestType::Local
+                    && has_extension(mth.path().as_path(), &config.attachment_extensions))
+            {
+                continue;
+            }
+
+            let image = match dest {
+                DestType::Local => {
+                    let path = match (&mth.kind, &vault) {
+                        (DestinationKind::WikiEmbed { .. }, Some(vault)) => vault
+                            .resolve(base_dir, mth.path().as_path())
+                            .unwrap_or_else(|| base_dir.join(mth.path())),
+                        _ => base_dir.join(mth.path()),
+                    };
+                    Image::Local(canonicalize(&path).unwrap_or(path))
+                }
+                DestType::Data if options.data_uri => Image::Data(mth.dest.clone()),
+                DestType::Remote if options.rehost => match rehost_url(&mth.dest, config) {
+                    Some(url) => Image::Remote(url),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            occurrences.entry(image).or_default().push((idx, mth));
+        }
+    }
+
+    // check out the result of every occurrence, return the replacements, i.e.,
+    // (index of the document, (range, replacement)).
+    let report = |image: &Image, mths: Occurrences, url: Uploaded| {
+        let image_path = image.display_path();
+        let mut res = res.lock().unwrap();
+        mths.into_iter()
+            .filter_map(|(idx, mth)| {
+                res.res_handling(&url, image_path.as_path()).map(|url| {
+                    let video =
+                        config.video_tag && has_extension(image_path.as_path(), &VIDEO_EXTENSIONS);
+                    let replacement = video
+                        .then(|| mth.video_tag(&url))
+                        .flatten()
+                        .unwrap_or_else(|| (mth.range.clone(), mth.escape(&url)));
+                    (idx, replacement)
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // load the images in parallel, then upload the loaded ones together
+    let (loaded, failed): (Vec<_>, Vec<_>) =
+        occurrences.into_par_iter().partition_map(|(image, mths)| {
+            // an image shared by multiple documents is named after the first one
+            let doc = docs[mths[0].0].path.as_deref();
+            match load_image(&downloader, &image) {
+                Ok((file_name, contents)) => Either::Left((
+                    (image, mths),
+                    NewFile {
+                        file_name,
+                        contents,
+                        doc,
+                    },
+                )),
+                Err(msg) => Either::Right((image, mths, Err(msg))),
+            }
+        });
+    let (images, files): (Vec<_>, Vec<_>) = loaded.into_iter().unzip();
+    let uploaded = images
+        .into_iter()
+        .zip(backend.upload_all(&files))
+        .map(|((image, mths), file)| (image, mths, file.map(|file| file.url)))
+        .chain(failed)
+        .flat_map(|(image, mths, url)| report(&image, mths, url.map_err(Into::into)))
+        .collect::<Vec<_>>();
+
+    let mut replacements = vec![Vec::new(); docs.len()];
+    for (idx, replacement) in uploaded {
+        replacements[idx].push(replacement);
+    }
+
+    // an image inside a link turned into a `<video>` tag is dropped by
+    // `replace`, the tag replaces the whole link
+    docs.iter()
+        .zip(replacements)
+        .map(|(doc, replacements)| {
+            (!replacements.is_empty()).then(|| replace(&doc.contents, replacements))
+        })
+        .collect()
+}
+
+/// Deal with every matched image, return the URL of the uploaded image.
+///
+/// This is the helper function used inside of [`img_manipulate`].
+fn manipulate_image(backend: &dyn Backend, downloader: &Downloader, image: &Image) -> Uploaded {
+    let (file_name, contents) = load_image(downloader, image)?;
+    let uploaded = backend.upload(&file_name, &contents, None)?;
+    Ok(uploaded.url)
+}
+
+/// Return the file name and the contents of `image`, a remote image is
+/// downloaded.
+fn load_image(downloader: &Downloader, image: &Image) -> anyhow::Result<(String, Vec<u8>)> {
+    match image {
+        Image::Local(image_path) => {
+            let file_name = image_path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .ok_or_else(|| anyhow!("invalid file name {:?}", image_path))?;
+            Ok((file_name.to_string(), read(image_path)?))
+        }
+        Image::Data(uri) => {
+            let data = DataUri::parse(uri)?;
+            Ok((data.file_name()?, data.contents))
+        }
+        Image::Remote(url) => {
+            let image = downloader.download(url)?;
+            Ok((image.file_name, image.contents))
+        }
+    }
+}
+
+/// Return the URL of `dest` if it should be rehosted, i.e., it is not on the
+/// configured repo and its host is allowed by the configuration.
+fn rehost_url(dest: &str, config: &UserConfig) -> Option<Url> {
+    let url = remote_url(dest)?;
+    let host = url.host_str()?;
+
+    let on_own_repo = is_own_url(config, &url);
+    let allowed = config.rehost_allowlist.is_empty()
+        || config
+            .rehost_allowlist
+            .iter()
+            .any(|pattern| host_matches(host, pattern));
+    let denied = config
+        .rehost_denylist
+        .iter()
+        .any(|pattern| host_matches(host, pattern));
+
+    (!on_own_repo && allowed && !denied).then_some(url)
 }
 
 /// Places `contents` into the clipboard.
@@ -95,16 +360,87 @@ fn clipboard_set(contents: &str) {
         .expect("can not store the returned URL to clipboard");
 }
 
-/// Manipulate single image file
-pub fn img_manipulate(target_file: &TargetFile, config: &UserConfig) {
-    let uploader = Uploader::init(config.token.as_ref().unwrap());
-    let response = uploader
-        .upload(target_file.file_path.as_path(), config)
-        .expect("Failed to upload");
+/// Upload image files and rehost remote images, the returned URLs are stored
+/// into the clipboard, one per line.
+pub fn img_manipulate(target_files: &[TargetFile], urls: &[Url], config: &UserConfig) {
+    let backend = backend::init(config);
+    let downloader = Downloader::init();
+
+    let images = target_files
+        .iter()
+        .map(|target_file| Image::Local(target_file.file_path.clone()))
+        .chain(urls.iter().map(|url| Image::Remote(url.clone())));
+    let mut new_urls = Vec::new();
+    for image in images {
+        let image_path = image.display_path();
+        match manipulate_image(backend.as_ref(), &downloader, &image) {
+            Ok(url) => {
+                println!("{} [{}]", image_path.display(), "done".green());
+                println!("URL: {}", url.as_str().purple());
+                new_urls.push(url);
+            }
+            Err(msg) => {
+                println!("{} [{}]: {:?}", image_path.display(), "FAILED".red(), msg);
+            }
+        }
+    }
+
+    if !new_urls.is_empty() {
+        clipboard_set(new_urls.join("\n").as_str());
+        println!("(URLs stored to your clipboard)");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test_config;
+    use crate::util::temp_dir::TempDir;
+
+    fn rehosted(config: &UserConfig, dest: &str) -> bool {
+        rehost_url(dest, config).is_some()
+    }
+
+    #[test]
+    fn test_read_document() {
+        let dir = TempDir::new("read");
+        let path = dir.write("a.md", b"![](a.png)");
+        assert_eq!(read_document(&path).unwrap().contents, "![](a.png)");
+        assert_eq!(
+            read_document(&path).unwrap().base_dir,
+            canonicalize(dir.path()).unwrap()
+        );
+
+        let path = dir.write("b.md", b"\xff![](a.png)");
+        assert!(read_document(&path).is_err());
+
+        assert!(read_document(Path::new("no/such/file.md")).is_err());
+    }
+
+    #[test]
+    fn test_rehost_url() {
+        let cfg = test_config("");
+        assert!(rehosted(&cfg, "https://example.com/a.png"));
+        assert!(rehosted(&cfg, "//cdn.example.com/a.png"));
+        assert!(!rehosted(&cfg, "a.png"));
+        // already on the repo
+        assert!(!rehosted(
+            &cfg,
+            "https://raw.githubusercontent.com/user/repo/main/a.png"
+        ));
 
-    let url = get_url(response).expect("failed to extract URL from the response message");
-    clipboard_set(url.as_str());
+        let cfg = test_config("rehost_allowlist = [\"example.com\"]");
+        assert!(rehosted(&cfg, "https://example.com/a.png"));
+        assert!(rehosted(&cfg, "https://img.example.com/a.png"));
+        assert!(!rehosted(&cfg, "https://notexample.com/a.png"));
+        assert!(!rehosted(&cfg, "https://other.org/a.png"));
 
-    println!("{} [{}]", target_file.file_path.display(), "done".green());
-    println!("URL: {} (stored to your clipboard)", url.as_str().purple());
+        // the denylist wins
+        let cfg = test_config(
+            "rehost_allowlist = [\"example.com\"]\nrehost_denylist = [\"private.example.com\"]",
+        );
+        assert!(rehosted(&cfg, "https://img.example.com/a.png"));
+        assert!(!rehosted(&cfg, "https://private.example.com/a.png"));
+        assert!(!rehosted(&cfg, "https://a.private.example.com/a.png"));
+    }
 }
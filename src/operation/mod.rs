@@ -1,23 +1,27 @@
-///! Operations supported by `pup`.
-///
-/// See `Operation` for more details.
+//! Operations supported by `pup`.
+//!
+//! See `Operation` for more details.
+pub mod download;
 pub mod manipulation;
 pub mod token;
 
 use crate::{
-    config::UserConfig,
+    config::{ConfigOverrides, UserConfig},
     util::{
-        adjust_pwd::adjust_pwd,
+        dest_type::{dest_type, remote_url, DestType},
         file_type::{file_type, FileType},
         result::MdManipulationResult,
+        walk::markdown_files,
     },
     CliApp,
 };
 use anyhow::{anyhow, Result};
-use manipulation::{img_manipulate, md_manipulate};
+use download::md_download;
+use manipulation::{img_manipulate, md_filter, md_manipulate};
+use reqwest::Url;
 use std::{
+    fs::canonicalize,
     path::{Path, PathBuf},
-    process::exit,
     sync::{Arc, Mutex},
 };
 use token::{delete_token, set_token, update_token};
@@ -39,13 +43,118 @@ pub enum Operation {
     /// $ pup --delete-token
     /// ```
     Token(TokenOperation),
-    /// File manipulation
+    /// File manipulation, the markdown files are manipulated together.
     ///
     /// ```shell
     /// $ pup xxx.md
-    /// $ pup xxx.jpeg/jpg/png/gif
+    /// $ pup xxx.jpeg/jpg/png/gif/webp/svg/avif/bmp/tiff/ico/heic
+    /// $ pup https://example.com/xxx.png
+    /// $ pup *.md *.png
+    /// $ pup docs
+    /// $ pup --include 'posts/**' --exclude 'drafts' docs
+    /// $ pup --data-uri xxx.md
+    /// $ pup --rehost xxx.md
+    /// $ pup --attachments xxx.md
     /// ```
-    File(TargetFile),
+    Files(Targets, MdOptions),
+    /// Read a markdown document from stdin and write the manipulated one to
+    /// stdout, relative paths are relative to the base directory.
+    ///
+    /// ```shell
+    /// $ pup - < xxx.md > yyy.md
+    /// $ pup --base-dir docs - < docs/xxx.md
+    /// ```
+    Filter(PathBuf, MdOptions),
+    /// Download the remote images of markdown files, the reverse of `Files`
+    ///
+    /// ```shell
+    /// $ pup --download xxx.md
+    /// $ pup --download docs
+    /// ```
+    Download(Vec<TargetFile>),
+}
+
+/// Targets of the file manipulation
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Targets {
+    /// Markdown files, including the ones found in the directories
+    pub markdown_files: Vec<TargetFile>,
+    /// Image files
+    pub images: Vec<TargetFile>,
+    /// URLs of the remote images to rehost
+    pub urls: Vec<Url>,
+}
+
+impl Targets {
+    /// Classify `file_paths`, directories are walked to find the markdown files
+    /// matched by `include` and `exclude`.
+    ///
+    /// # Error
+    /// A path does not exist and is not a remote URL, it is a remote URL that
+    /// can not be parsed, it is not a markdown or image file, or it is a
+    /// directory that can not be walked.
+    fn new(file_paths: &[PathBuf], include: &[String], exclude: &[String]) -> Result<Self> {
+        let mut targets = Targets::default();
+        for file_path in file_paths {
+            if let Some(url) = file_path.to_str() {
+                if dest_type(url, &[]) == DestType::Remote && !file_path.exists() {
+                    let url = remote_url(url).ok_or_else(|| anyhow!("Invalid URL {}.", url))?;
+                    targets.urls.push(url);
+                    continue;
+                }
+            }
+
+            if !file_path.exists() {
+                return Err(anyhow!(format!(
+                    "File {} does not exist.",
+                    file_path.display()
+                )));
+            }
+
+            if file_path.is_dir() {
+                let files = markdown_files(file_path, include, exclude)?;
+                targets
+                    .markdown_files
+                    .extend(files.iter().map(|path| TargetFile::new(path)));
+                continue;
+            }
+
+            let target_file = TargetFile::new(file_path);
+            match target_file.file_type {
+                FileType::Markdown => targets.markdown_files.push(target_file),
+                FileType::Image => targets.images.push(target_file),
+                FileType::Unknown => {
+                    return Err(anyhow!(format!(
+                        "Unknown file type of {}, abort.",
+                        file_path.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(targets)
+    }
+}
+
+/// Options of the markdown file manipulation
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct MdOptions {
+    /// Upload the images embedded as `data:` URIs.
+    pub data_uri: bool,
+    /// Rehost the remote images.
+    pub rehost: bool,
+    /// Upload the local files linked by regular links as attachments.
+    pub attachments: bool,
+}
+
+impl From<&CliApp> for MdOptions {
+    fn from(value: &CliApp) -> Self {
+        MdOptions {
+            data_uri: value.data_uri,
+            rehost: value.rehost,
+            attachments: value.attachments,
+        }
+    }
 }
 
 /// Token-related Operations
@@ -78,8 +187,8 @@ impl TryFrom<&CliApp> for Operation {
     /// Try to convert from `CliApp` to `Operation`
     ///
     /// # Error
-    /// The ONLY error case that could occur is that the specified `filepath` does not
-    /// exist.
+    /// A specified path can not be a target, see [`Targets::new`], or the
+    /// options conflict with each other.
     fn try_from(value: &CliApp) -> Result<Self, Self::Error> {
         if value.set_token {
             return Ok(Operation::Token(TokenOperation::Set));
@@ -93,16 +202,31 @@ impl TryFrom<&CliApp> for Operation {
             return Ok(Operation::Token(TokenOperation::Delete));
         }
 
-        if let Some(ref file_path) = value.filepath {
-            if !file_path.exists() {
-                return Err(anyhow!(format!(
-                    "File {} does not exist.",
-                    file_path.display()
-                )));
+        if value
+            .filepaths
+            .iter()
+            .any(|file_path| file_path.as_os_str() == "-")
+        {
+            if value.filepaths.len() > 1 || value.download {
+                return Err(anyhow!(
+                    "`-` can not be used with other targets or `--download`."
+                ));
             }
+            let base_dir = value.base_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+            return Ok(Operation::Filter(base_dir, MdOptions::from(value)));
+        }
 
-            let target_file = TargetFile::new(file_path);
-            return Ok(Operation::File(target_file));
+        if !value.filepaths.is_empty() {
+            let targets = Targets::new(&value.filepaths, &value.include, &value.exclude)?;
+            if value.download {
+                if !targets.images.is_empty() || !targets.urls.is_empty() {
+                    return Err(anyhow!(
+                        "Only markdown files are supported by `--download`, abort."
+                    ));
+                }
+                return Ok(Operation::Download(targets.markdown_files));
+            }
+            return Ok(Operation::Files(targets, MdOptions::from(value)));
         }
 
         Ok(Operation::Check)
@@ -110,27 +234,55 @@ impl TryFrom<&CliApp> for Operation {
 }
 
 impl Operation {
-    /// Execute this `Operation`.
-    pub fn execute(&self) -> Result<()> {
+    /// Execute this `Operation`, the configuration is loaded with `overrides`.
+    pub fn execute(&self, overrides: &ConfigOverrides) -> Result<()> {
         match self {
             Operation::Check => {
-                UserConfig::load();
+                UserConfig::load(overrides);
             }
             Operation::Token(token_opt) => token_opt.execute()?,
-            Operation::File(target_file) => {
-                let user_config = UserConfig::load();
-                match target_file.file_type {
-                    FileType::Unknown => {
-                        eprintln!("Unknown file type, abort.");
-                        exit(1);
-                    }
-                    FileType::Markdown => {
-                        let result = Arc::new(Mutex::new(MdManipulationResult::default()));
-                        adjust_pwd(target_file.file_path.as_path());
-                        md_manipulate(target_file, &user_config, result);
-                    }
-                    FileType::Image => img_manipulate(target_file, &user_config),
-                };
+            Operation::Download(target_files) => {
+                let user_config = UserConfig::load(overrides);
+                let result = Arc::new(Mutex::new(MdManipulationResult::default()));
+                // resolve all the paths before any file is touched, the assets
+                // directory is relative to every markdown file
+                let target_files = target_files
+                    .iter()
+                    .map(|target_file| {
+                        canonicalize(target_file.file_path.as_path())
+                            .map(|path| TargetFile::new(&path))
+                            .map_err(|e| anyhow!("{}: {}", target_file.file_path.display(), e))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                for target_file in target_files.iter() {
+                    md_download(target_file, &user_config, Arc::clone(&result));
+                }
+                result.lock().unwrap().report();
+            }
+            Operation::Filter(base_dir, options) => {
+                let user_config = UserConfig::load(overrides);
+                let result = Arc::new(Mutex::new(MdManipulationResult::stderr()));
+                md_filter(base_dir, &user_config, *options, Arc::clone(&result));
+                result.lock().unwrap().report();
+            }
+            Operation::Files(targets, options) => {
+                let user_config = UserConfig::load(overrides);
+                if !targets.images.is_empty() || !targets.urls.is_empty() {
+                    img_manipulate(&targets.images, &targets.urls, &user_config);
+                }
+                // report the result even if a directory has no markdown files
+                if !targets.markdown_files.is_empty()
+                    || (targets.images.is_empty() && targets.urls.is_empty())
+                {
+                    let result = Arc::new(Mutex::new(MdManipulationResult::default()));
+                    md_manipulate(
+                        &targets.markdown_files,
+                        &user_config,
+                        *options,
+                        Arc::clone(&result),
+                    );
+                    result.lock().unwrap().report();
+                }
             }
         }
 
@@ -155,3 +307,46 @@ impl TargetFile {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    fn operation(args: &[&str]) -> Result<Operation> {
+        let app = CliApp::try_parse_from(["pup"].iter().chain(args)).unwrap();
+        Operation::try_from(&app)
+    }
+
+    #[test]
+    fn test_urls() {
+        let targets = match operation(&["//cdn.example.com/a.png"]).unwrap() {
+            Operation::Files(targets, _) => targets,
+            operation => panic!("{:?}", operation),
+        };
+        assert_eq!(
+            targets.urls,
+            [Url::parse("https://cdn.example.com/a.png").unwrap()]
+        );
+
+        assert!(operation(&["https://[::1/a.png"]).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        assert!(matches!(
+            operation(&["-"]),
+            Ok(Operation::Filter(base_dir, _)) if base_dir == Path::new(".")
+        ));
+        assert!(matches!(
+            operation(&["--base-dir", "docs", "-"]),
+            Ok(Operation::Filter(base_dir, _)) if base_dir == Path::new("docs")
+        ));
+
+        // `-` can only be used alone
+        assert!(operation(&["-", "a.md"]).is_err());
+        assert!(operation(&["a.md", "-"]).is_err());
+        assert!(operation(&["-", "-"]).is_err());
+        assert!(operation(&["--download", "-"]).is_err());
+    }
+}
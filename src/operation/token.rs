@@ -12,7 +12,7 @@ use std::{
 ///
 /// if it is not set yet, ask the user to input then store it.
 ///
-/// Used in `UserConfig::load()`.
+/// Used in `UserConfig::load`.
 pub fn fetch_token() -> String {
     let pup = Entry::new("pup", "pup").expect("expected it to be registered successfully");
 
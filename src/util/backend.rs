@@ -0,0 +1,398 @@
+//! Storage backends that the files are uploaded to, selected by
+//! `UserConfig.backend`.
+//!
+//! Every backend implements [`Backend`], the rest of `pup` does not know where
+//! the files are stored. The backends pick the paths and the commit messages of
+//! the files in the same way, see [`upload_path`] and [`commit_message`].
+
+use crate::{
+    config::{BackendKind, Naming, UserConfig},
+    util::{
+        file_type::{check_image, content_file_name, file_type, FileType},
+        gitlab::GitLab,
+        request::{GitHub, Gitea},
+        s3::S3,
+        template::{expand, today},
+    },
+};
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::HeaderMap,
+    StatusCode,
+};
+use serde_json::{from_str, Value};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// A storage backend
+pub trait Backend: Sync {
+    /// Upload `contents` as file `file_name`.
+    ///
+    /// `doc` is the markdown document that references the file, if any, the
+    /// backend may use it to name the file.
+    ///
+    /// The path of the file is picked by [`upload_path`], and the commit
+    /// message, if any, by [`commit_message`]. Files are never overwritten,
+    /// the documents may reference them: if a file with the same contents
+    /// exists there, it is treated as uploaded.
+    ///
+    /// # Error
+    /// A different file exists there, or the file can not be uploaded.
+    fn upload(&self, file_name: &str, contents: &[u8], doc: Option<&Path>) -> Result<UploadedFile>;
+
+    /// Upload all the `files`, return the result of every file in the same
+    /// order.
+    ///
+    /// The files are uploaded one by one in parallel by default, see
+    /// [`upload_each`].
+    fn upload_all(&self, files: &[NewFile<'_>]) -> Vec<Result<UploadedFile>> {
+        upload_each(self, files)
+    }
+
+    /// Return true if a file exists at `path` of the backend.
+    // `exists` and `delete` are part of the interface of every backend, no
+    // command uses them yet
+    #[allow(dead_code)]
+    fn exists(&self, path: &str) -> Result<bool>;
+
+    /// Delete the file at `path` of the backend.
+    ///
+    /// # Error
+    /// The file does not exist or can not be deleted.
+    #[allow(dead_code)]
+    fn delete(&self, path: &str) -> Result<()>;
+}
+
+/// A file uploaded to a backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedFile {
+    /// Path of the file in the backend
+    pub path: String,
+    /// URL of the file
+    pub url: String,
+}
+
+/// A file to be uploaded by [`Backend::upload_all`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewFile<'a> {
+    /// File name
+    pub file_name: String,
+    /// File contents
+    pub contents: Vec<u8>,
+    /// The markdown document that references the file, if any
+    pub doc: Option<&'a Path>,
+}
+
+/// Upload `files` one by one in parallel with [`Backend::upload`].
+pub fn upload_each<B: Backend + ?Sized>(
+    backend: &B,
+    files: &[NewFile<'_>],
+) -> Vec<Result<UploadedFile>> {
+    files
+        .par_iter()
+        .map(|file| backend.upload(&file.file_name, &file.contents, file.doc))
+        .collect()
+}
+
+/// Initialize the backend selected by `UserConfig.backend`.
+pub fn init(config: &UserConfig) -> Box<dyn Backend + '_> {
+    match config.backend {
+        BackendKind::GitHub => Box::new(GitHub::init(config)),
+        BackendKind::GitLab => Box::new(GitLab::init(config)),
+        BackendKind::Gitea => Box::new(Gitea::init(config)),
+        BackendKind::S3 => Box::new(S3::init(config)),
+    }
+}
+
+/// File name of the markdown document `doc`, empty if there is no document.
+pub fn doc_name(doc: Option<&Path>) -> String {
+    doc.and_then(|doc| doc.file_name())
+        .map(|doc| doc.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Commit message of uploading `file`, i.e., `UserConfig.commit_message`
+/// expanded with the variables:
+///
+/// * `{file}`: the path of the file in the backend
+/// * `{doc}`: the file name of the markdown document, empty if there is no
+///   document
+/// * `{host}`: the host name of this machine
+///
+/// For a [`Batch`](crate::util::request::Batch), `file` and `doc` are lists separated by `, `.
+pub fn commit_message(file: &str, doc: &str, user_cfg: &UserConfig) -> Result<String> {
+    expand(&user_cfg.commit_message, |variable| {
+        let value = match variable {
+            "file" => file.to_string(),
+            "doc" => doc.to_string(),
+            "host" => hostname::get()
+                .map(|host| host.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            _ => return None,
+        };
+        Some(value)
+    })
+}
+
+/// Check `contents`, originally named `file_name`, and return its path in the
+/// backend, see [`remote_path`].
+///
+/// `doc` is the markdown document that references the file, if any.
+///
+/// Every backend picks the paths in this way, so the same configuration gives
+/// the same paths on all of them.
+///
+/// # Error
+/// The file is an image whose contents do not match its extension, or the
+/// path can not be picked.
+pub fn upload_path(
+    file_name: &str,
+    contents: &[u8],
+    doc: Option<&Path>,
+    user_cfg: &UserConfig,
+) -> Result<String> {
+    if file_type(file_name) == FileType::Image {
+        check_image(file_name, contents)?;
+    }
+    let doc_stem = doc
+        .and_then(|doc| doc.file_stem())
+        .map(|stem| stem.to_string_lossy());
+    remote_path(file_name, contents, doc_stem.as_deref(), user_cfg)
+}
+
+/// Path of `contents`, originally named `file_name`, in the backend.
+///
+/// It is `UserConfig.path_template` expanded if it is set, with the
+/// variables:
+///
+/// * `{yyyy}`, `{mm}`, `{dd}`: today's date (UTC)
+/// * `{name}`, `{stem}`, `{ext}`: the original file name, without the extension,
+///   and the extension
+/// * `{hash}`, `{hash8}`: the first 16 and 8 hex digits of the SHA-256 of the
+///   contents
+/// * `{doc_stem}`: the name of the markdown document without the extension,
+///   empty if there is no document
+/// * `{random}`: a random id of 8 hex digits
+///
+/// Empty path segments are removed. Otherwise, the file is named according to
+/// `UserConfig.naming`.
+fn remote_path(
+    file_name: &str,
+    contents: &[u8],
+    doc_stem: Option<&str>,
+    user_cfg: &UserConfig,
+) -> Result<String> {
+    let template = match (&user_cfg.path_template, user_cfg.naming) {
+        (Some(template), _) => template,
+        (None, Naming::Original) => return Ok(file_name.to_string()),
+        (None, Naming::Hash) => return Ok(content_file_name(file_name, contents)),
+    };
+
+    let (year, month, day) = today();
+    let path = Path::new(file_name);
+    let hash = format!("{:.16x}", Sha256::digest(contents));
+    let expanded = expand(template, |variable| {
+        let value = match variable {
+            "yyyy" => format!("{:04}", year),
+            "mm" => format!("{:02}", month),
+            "dd" => format!("{:02}", day),
+            "name" => file_name.to_string(),
+            "stem" => path.file_stem()?.to_string_lossy().to_string(),
+            "ext" => path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default(),
+            "hash" => hash.clone(),
+            "hash8" => hash[..8].to_string(),
+            "doc_stem" => doc_stem.unwrap_or_default().to_string(),
+            "random" => format!("{:08x}", fastrand::u32(..)),
+            _ => return None,
+        };
+        Some(value)
+    })?;
+
+    let path = expanded
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.is_empty() {
+        return Err(anyhow!("`path_template` expands to an empty path"));
+    }
+
+    Ok(path)
+}
+
+/// Send `req` with `header`, return the JSON response body.
+///
+/// # Error
+/// The request fails or the response status is not successful, see
+/// [`json_body`].
+pub fn send_json(req: RequestBuilder, header: &HeaderMap) -> Result<Value> {
+    json_body(req.headers(header.clone()).send()?)
+}
+
+/// Send `req` with `header`, return the JSON response body, or `None` if the
+/// status is 404, e.g., the requested file does not exist.
+///
+/// # Error
+/// The request fails or the response status is neither successful nor 404.
+pub fn fetch_json(req: RequestBuilder, header: &HeaderMap) -> Result<Option<Value>> {
+    let res = req.headers(header.clone()).send()?;
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    json_body(res).map(Some)
+}
+
+/// Return the JSON body of `res`.
+///
+/// # Error
+/// The response status is not successful, the error contains the `message` of
+/// the body, see [`error_message`].
+fn json_body(res: Response) -> Result<Value> {
+    let status = res.status();
+    let body: Value = from_str(&res.text()?).unwrap_or_default();
+    if !status.is_success() {
+        return Err(anyhow!("{}: {}", status, error_message(&body)));
+    }
+
+    Ok(body)
+}
+
+/// The `message` of an error response body, which is a string for GitHub and
+/// Gitea, and can be an object of the invalid fields for GitLab.
+fn error_message(body: &Value) -> String {
+    match body["message"] {
+        Value::String(ref message) => message.clone(),
+        Value::Null => String::new(),
+        ref message => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test_config;
+    use anyhow::anyhow;
+    use regex::Regex;
+
+    /// A backend that fails to upload empty files.
+    #[derive(Debug)]
+    struct Fake;
+
+    impl Backend for Fake {
+        fn upload(
+            &self,
+            file_name: &str,
+            contents: &[u8],
+            _doc: Option<&Path>,
+        ) -> Result<UploadedFile> {
+            if contents.is_empty() {
+                return Err(anyhow!("empty"));
+            }
+            Ok(UploadedFile {
+                path: file_name.to_string(),
+                url: format!("https://example.com/{}", file_name),
+            })
+        }
+
+        fn exists(&self, _path: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn delete(&self, path: &str) -> Result<()> {
+            Err(anyhow!("{} does not exist", path))
+        }
+    }
+
+    #[test]
+    fn test_remote_path() {
+        let hash8 = format!("{:.8x}", Sha256::digest(b"x"));
+        assert_eq!(
+            remote_path("a.PNG", b"x", None, &test_config("")).unwrap(),
+            "a.PNG"
+        );
+        assert_eq!(
+            remote_path("a.png", b"x", None, &test_config("naming = \"hash\"")).unwrap(),
+            format!("{:.16x}.png", Sha256::digest(b"x"))
+        );
+
+        let cfg = test_config("path_template = \"{doc_stem}/{stem}-{hash8}.{ext}\"");
+        assert_eq!(
+            remote_path("a.PNG", b"x", Some("post"), &cfg).unwrap(),
+            format!("post/a-{}.png", hash8)
+        );
+        assert_eq!(
+            remote_path("a.PNG", b"x", None, &cfg).unwrap(),
+            format!("a-{}.png", hash8)
+        );
+
+        let cfg = test_config("path_template = \"{yyyy}/{mm}/{dd}/{random}-{name}\"");
+        let path = remote_path("a.png", b"x", None, &cfg).unwrap();
+        let re = Regex::new(r"^\d{4}/\d{2}/\d{2}/[0-9a-f]{8}-a\.png$").unwrap();
+        assert!(re.is_match(&path), "{}", path);
+
+        assert!(remote_path(
+            "a.png",
+            b"x",
+            None,
+            &test_config("path_template = \"{nope}\"")
+        )
+        .is_err());
+        assert!(remote_path(
+            "a.png",
+            b"x",
+            None,
+            &test_config("path_template = \"{doc_stem}\"")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_commit_message() {
+        let cfg = test_config("commit_message = \"add {file} from {doc}\"");
+        assert_eq!(
+            commit_message("a.png", &doc_name(Some(Path::new("docs/post.md"))), &cfg).unwrap(),
+            "add a.png from post.md"
+        );
+        assert_eq!(
+            commit_message("a.png", &doc_name(None), &test_config("")).unwrap(),
+            "upload a.png"
+        );
+        assert!(commit_message("a.png", "", &test_config("commit_message = \"{nope}\"")).is_err());
+    }
+
+    #[test]
+    fn test_error_message() {
+        let body: Value = from_str(r#"{"message": "Not Found"}"#).unwrap();
+        assert_eq!(error_message(&body), "Not Found");
+        let body: Value = from_str(r#"{"message": {"branch": ["is invalid"]}}"#).unwrap();
+        assert_eq!(error_message(&body), r#"{"branch":["is invalid"]}"#);
+        assert_eq!(error_message(&Value::Null), "");
+    }
+
+    #[test]
+    fn test_upload_all() {
+        let files = ["a.png", "b.png", "c.png"].map(|file_name| NewFile {
+            file_name: file_name.to_string(),
+            contents: if file_name == "b.png" {
+                vec![]
+            } else {
+                vec![1]
+            },
+            doc: None,
+        });
+        let uploaded = Fake.upload_all(&files);
+        assert_eq!(uploaded.len(), 3);
+        assert_eq!(
+            uploaded[0].as_ref().unwrap().url,
+            "https://example.com/a.png"
+        );
+        assert!(uploaded[1].is_err());
+        assert_eq!(uploaded[2].as_ref().unwrap().path, "c.png");
+    }
+}
@@ -0,0 +1,108 @@
+//! Decode `data:` URIs, e.g., `data:image/png;base64,iVBORw0KGgo...`
+
+use crate::util::file_type::hashed_file_name;
+use anyhow::{anyhow, Result};
+use base64::{
+    alphabet::STANDARD,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use percent_encoding::percent_decode_str;
+
+/// Base64 engine that accepts data with or without padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Type to represent a decoded `data:` URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataUri {
+    /// MIME type, `text/plain` if absent
+    pub mime: String,
+    /// Decoded contents
+    pub contents: Vec<u8>,
+}
+
+impl DataUri {
+    /// Parse a `data:[<mediatype>][;base64],<data>` URI.
+    pub fn parse(uri: &str) -> Result<Self> {
+        let (scheme, rest) = uri
+            .split_once(':')
+            .ok_or_else(|| anyhow!("not a data URI"))?;
+        if !scheme.eq_ignore_ascii_case("data") {
+            return Err(anyhow!("not a data URI"));
+        }
+        let (media_type, data) = rest
+            .split_once(',')
+            .ok_or_else(|| anyhow!("data URI without `,`"))?;
+
+        let mut params = media_type.split(';').map(str::trim);
+        let mime = match params.next() {
+            Some(mime) if !mime.is_empty() => mime.to_ascii_lowercase(),
+            _ => "text/plain".to_string(),
+        };
+        let is_base64 = params.any(|param| param.eq_ignore_ascii_case("base64"));
+
+        let data = percent_decode_str(data).collect::<Vec<u8>>();
+        let contents = if is_base64 {
+            let data = data
+                .into_iter()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect::<Vec<u8>>();
+            BASE64.decode(data)?
+        } else {
+            data
+        };
+
+        Ok(Self { mime, contents })
+    }
+
+    /// Pick a file name from the MIME type and the hash of the contents.
+    pub fn file_name(&self) -> Result<String> {
+        hashed_file_name(&self.contents, &self.mime)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        let uri = DataUri::parse("data:image/PNG;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(uri.mime, "image/png");
+        assert_eq!(uri.contents, b"\x89PNG\r\n\x1a\n");
+
+        // padding is optional
+        let uri = DataUri::parse("data:image/png;base64,iVBORw0KGgo").unwrap();
+        assert_eq!(uri.contents, b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn test_percent_encoded() {
+        let uri = DataUri::parse("data:image/svg+xml,%3Csvg%3E%3C/svg%3E").unwrap();
+        assert_eq!(uri.mime, "image/svg+xml");
+        assert_eq!(uri.contents, b"<svg></svg>");
+        assert!(uri.file_name().unwrap().ends_with(".svg"));
+    }
+
+    #[test]
+    fn test_file_name() {
+        let uri = DataUri::parse("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        let name = uri.file_name().unwrap();
+        assert_eq!(name.len(), "0123456789abcdef.png".len());
+        assert_eq!(name, uri.file_name().unwrap());
+
+        let uri = DataUri::parse("data:,hello").unwrap();
+        assert_eq!(uri.mime, "text/plain");
+        assert!(uri.file_name().is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(DataUri::parse("https://x.com/x.png").is_err());
+        assert!(DataUri::parse("data:image/png;base64").is_err());
+        assert!(DataUri::parse("data:image/png;base64,!!!").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Contains utilities used to classify image destinations.
+
+use regex::Regex;
+use reqwest::Url;
+
+/// Image destination types
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DestType {
+    /// Path to a local file
+    Local,
+    /// `http://`, `https://` or protocol-relative (`//host/...`) URL
+    Remote,
+    /// `data:` URI
+    Data,
+    /// URL of any other scheme, e.g., `mailto:` or `ftp://`
+    OtherScheme,
+    /// Destination that is matched by one of the `skip_patterns`
+    Skipped,
+}
+
+/// Construct a [`DestType`] according to the given destination `dest`.
+///
+/// `skip_patterns` are checked before the scheme.
+pub fn dest_type(dest: &str, skip_patterns: &[Regex]) -> DestType {
+    if skip_patterns.iter().any(|pattern| pattern.is_match(dest)) {
+        return DestType::Skipped;
+    }
+
+    if dest.starts_with("//") {
+        return DestType::Remote;
+    }
+
+    match scheme(dest) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("http") => DestType::Remote,
+        Some(scheme) if scheme.eq_ignore_ascii_case("https") => DestType::Remote,
+        Some(scheme) if scheme.eq_ignore_ascii_case("data") => DestType::Data,
+        Some(_) => DestType::OtherScheme,
+        None => DestType::Local,
+    }
+}
+
+/// Parse a [`DestType::Remote`] destination, a protocol-relative URL is
+/// treated as a `https://` one.
+pub fn remote_url(dest: &str) -> Option<Url> {
+    let url = if dest.starts_with("//") {
+        Url::parse(&format!("https:{}", dest)).ok()?
+    } else {
+        Url::parse(dest).ok()?
+    };
+
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+/// Check if `host` is `pattern` or a subdomain of it, e.g., `img.example.com`
+/// matches `example.com`.
+pub fn host_matches(host: &str, pattern: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    let pattern = pattern.trim_start_matches('.');
+
+    host.eq_ignore_ascii_case(pattern)
+        || (host.len() > pattern.len()
+            && host.as_bytes()[host.len() - pattern.len() - 1] == b'.'
+            && host[host.len() - pattern.len()..].eq_ignore_ascii_case(pattern))
+}
+
+/// Return the scheme of `dest`, an ASCII letter followed by any combination
+/// of ASCII letters, digits, `+`, `-` and `.`, and terminated by a `:`.
+fn scheme(dest: &str) -> Option<&str> {
+    let (scheme, _) = dest.split_once(':')?;
+    let mut chars = scheme.chars();
+
+    if chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local() {
+        assert_eq!(DestType::Local, dest_type("x.png", &[]));
+        assert_eq!(DestType::Local, dest_type("/home/steve/x.png", &[]));
+        assert_eq!(DestType::Local, dest_type("../img/a:b.png", &[]));
+        assert_eq!(DestType::Local, dest_type("1x:y.png", &[]));
+    }
+
+    #[test]
+    fn test_remote() {
+        assert_eq!(DestType::Remote, dest_type("https://x.com/x.png", &[]));
+        assert_eq!(DestType::Remote, dest_type("HTTP://x.com/x.png", &[]));
+        assert_eq!(DestType::Remote, dest_type("//cdn.x.com/x.png", &[]));
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(DestType::Data, dest_type("data:image/png;base64,AAAA", &[]));
+    }
+
+    #[test]
+    fn test_other_scheme() {
+        assert_eq!(DestType::OtherScheme, dest_type("mailto:x@x.com", &[]));
+        assert_eq!(DestType::OtherScheme, dest_type("ftp://x.com/x.png", &[]));
+    }
+
+    #[test]
+    fn test_remote_url() {
+        assert_eq!(
+            remote_url("//cdn.x.com/x.png").unwrap().as_str(),
+            "https://cdn.x.com/x.png"
+        );
+        assert_eq!(
+            remote_url("HTTP://x.com/x.png").unwrap().as_str(),
+            "http://x.com/x.png"
+        );
+        assert!(remote_url("ftp://x.com/x.png").is_none());
+        assert!(remote_url("x.png").is_none());
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("img.Example.com", "example.com"));
+        assert!(host_matches("img.example.com", ".example.com"));
+        assert!(!host_matches("badexample.com", "example.com"));
+        assert!(!host_matches("example.com", "img.example.com"));
+    }
+
+    #[test]
+    fn test_skipped() {
+        let patterns = [
+            Regex::new(r"^\{\{\s*site\.baseurl\s*\}\}").unwrap(),
+            Regex::new(r"^https://cdn\.corp\.com/").unwrap(),
+        ];
+        assert_eq!(
+            DestType::Skipped,
+            dest_type("{{ site.baseurl }}/x.png", &patterns)
+        );
+        assert_eq!(
+            DestType::Skipped,
+            dest_type("https://cdn.corp.com/x.png", &patterns)
+        );
+        assert_eq!(DestType::Local, dest_type("x.png", &patterns));
+    }
+}
@@ -1,5 +1,7 @@
 //! Contains utilities used to deal with target file type.
 
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
 use std::path::Path;
 
 /// File types that are supported by `pup`
@@ -10,15 +12,185 @@ pub enum FileType {
     Unknown,
 }
 
+/// Image formats that are supported by `pup`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Svg,
+    Avif,
+    Bmp,
+    Tiff,
+    Ico,
+    Heic,
+}
+
+impl ImageFormat {
+    /// Construct an [`ImageFormat`] from a file extension, case-insensitively.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "gif" => Some(ImageFormat::Gif),
+            "webp" => Some(ImageFormat::Webp),
+            "svg" => Some(ImageFormat::Svg),
+            "avif" => Some(ImageFormat::Avif),
+            "bmp" => Some(ImageFormat::Bmp),
+            "tiff" | "tif" => Some(ImageFormat::Tiff),
+            "ico" => Some(ImageFormat::Ico),
+            "heic" | "heif" => Some(ImageFormat::Heic),
+            _ => None,
+        }
+    }
+
+    /// Construct an [`ImageFormat`] from a MIME type.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            "image/gif" => Some(ImageFormat::Gif),
+            "image/webp" => Some(ImageFormat::Webp),
+            "image/svg+xml" => Some(ImageFormat::Svg),
+            "image/avif" => Some(ImageFormat::Avif),
+            "image/bmp" => Some(ImageFormat::Bmp),
+            "image/tiff" => Some(ImageFormat::Tiff),
+            "image/x-icon" | "image/vnd.microsoft.icon" => Some(ImageFormat::Ico),
+            "image/heic" | "image/heif" => Some(ImageFormat::Heic),
+            _ => None,
+        }
+    }
+
+    /// Sniff the format from the header of the file `contents`.
+    pub fn from_contents(contents: &[u8]) -> Option<Self> {
+        match contents {
+            [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(ImageFormat::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(ImageFormat::Webp)
+            }
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(ImageFormat::Tiff),
+            [0x00, 0x00, 0x01, 0x00, ..] => Some(ImageFormat::Ico),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => ftyp_format(contents),
+            _ if is_svg(contents) => Some(ImageFormat::Svg),
+            _ => None,
+        }
+    }
+
+    /// The MIME type of this format.
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Svg => "image/svg+xml",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Bmp => "image/bmp",
+            ImageFormat::Tiff => "image/tiff",
+            ImageFormat::Ico => "image/x-icon",
+            ImageFormat::Heic => "image/heic",
+        }
+    }
+
+    /// The canonical file extension of this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Svg => "svg",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Ico => "ico",
+            ImageFormat::Heic => "heic",
+        }
+    }
+}
+
+/// Sniff the format of an ISO base media file, AVIF or HEIC, from the brands
+/// listed in its `ftyp` box.
+fn ftyp_format(contents: &[u8]) -> Option<ImageFormat> {
+    let box_size = u32::from_be_bytes(contents[..4].try_into().unwrap()) as usize;
+    let ftyp = &contents[..box_size.clamp(8, contents.len())];
+    // major brand, minor version and then the compatible brands
+    let brands = ftyp
+        .get(8..)?
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(idx, _)| *idx != 1)
+        .map(|(_, brand)| brand)
+        .collect::<Vec<_>>();
+
+    if brands
+        .iter()
+        .any(|brand| matches!(*brand, b"avif" | b"avis"))
+    {
+        Some(ImageFormat::Avif)
+    } else if brands.iter().any(|brand| {
+        matches!(
+            *brand,
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"
+        )
+    }) {
+        Some(ImageFormat::Heic)
+    } else {
+        None
+    }
+}
+
+/// Check if `contents` looks like a SVG document, which is a XML document with
+/// a `<svg>` root element.
+fn is_svg(contents: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&contents[..contents.len().min(4096)]);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+
+    head.starts_with('<') && head.contains("<svg")
+}
+
+/// Check that the format of the image `contents` matches the extension of
+/// `file_name`, return the format.
+///
+/// An error is returned if the format can not be recognized or it does not
+/// match the extension, e.g., a `.png` file is actually a JPEG file.
+pub fn check_image(file_name: &str, contents: &[u8]) -> Result<ImageFormat> {
+    let format = ImageFormat::from_contents(contents)
+        .ok_or_else(|| anyhow!("{} is not an image of any supported format", file_name))?;
+    let ext_format = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ImageFormat::from_extension);
+
+    match ext_format {
+        Some(ext_format) if ext_format != format => Err(anyhow!(
+            "{} is actually a {:?} image, rather than {:?}",
+            file_name,
+            format,
+            ext_format
+        )),
+        _ => Ok(format),
+    }
+}
+
 /// Construct a [`FileType`] according to the given `path`
+///
+/// The extension is compared case-insensitively.
 pub fn file_type<P: AsRef<Path>>(path: P) -> FileType {
     match path.as_ref().extension() {
         Some(os_str_type) => {
             if let Some(str_type) = os_str_type.to_str() {
-                match str_type {
-                    "md" => FileType::Markdown,
-                    "jpeg" | "jpg" | "png" | "gif" => FileType::Image,
-                    _ => FileType::Unknown,
+                if str_type.eq_ignore_ascii_case("md") || str_type.eq_ignore_ascii_case("markdown")
+                {
+                    FileType::Markdown
+                } else if ImageFormat::from_extension(str_type).is_some() {
+                    FileType::Image
+                } else {
+                    FileType::Unknown
                 }
             } else {
                 FileType::Unknown
@@ -28,6 +200,30 @@ pub fn file_type<P: AsRef<Path>>(path: P) -> FileType {
     }
 }
 
+/// Pick a file name for `contents` from its MIME type and hash.
+///
+/// Used for the images that do not come with a file name.
+pub fn hashed_file_name(contents: &[u8], mime: &str) -> Result<String> {
+    let format =
+        ImageFormat::from_mime(mime).ok_or_else(|| anyhow!("unsupported MIME type `{}`", mime))?;
+    let hash = Sha256::digest(contents);
+
+    Ok(format!("{:.16x}.{}", hash, format.extension()))
+}
+
+/// Name `contents` after its hash, the extension of `file_name` is kept, so
+/// that the same contents are always uploaded to the same path.
+pub fn content_file_name(file_name: &str, contents: &[u8]) -> String {
+    let hash = Sha256::digest(contents);
+    match Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some(ext) => format!("{:.16x}.{}", hash, ext.to_ascii_lowercase()),
+        None => format!("{:.16x}", hash),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -40,9 +236,65 @@ mod test {
         assert_eq!(FileType::Image, file_type("x.gif"));
     }
 
+    #[test]
+    fn test_image_case_insensitive() {
+        assert_eq!(FileType::Image, file_type("IMG.PNG"));
+        assert_eq!(FileType::Image, file_type("x.Jpeg"));
+        for ext in ["webp", "svg", "avif", "bmp", "tiff", "tif", "ico", "heic"] {
+            assert_eq!(FileType::Image, file_type(format!("x.{}", ext)));
+        }
+    }
+
     #[test]
     fn test_markdown() {
         assert_eq!(FileType::Markdown, file_type("x.md"));
+        assert_eq!(FileType::Markdown, file_type("README.MD"));
+    }
+
+    #[test]
+    fn test_sniff() {
+        let cases: [(&[u8], ImageFormat); 12] = [
+            (b"\xFF\xD8\xFF\xE0\x00\x10JFIF", ImageFormat::Jpeg),
+            (b"\x89PNG\r\n\x1a\n\x00\x00", ImageFormat::Png),
+            (b"GIF89a\x01\x00", ImageFormat::Gif),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", ImageFormat::Webp),
+            (b"BM\x36\x00", ImageFormat::Bmp),
+            (b"II*\x00\x08\x00", ImageFormat::Tiff),
+            (b"MM\x00*\x00\x08", ImageFormat::Tiff),
+            (b"\x00\x00\x01\x00\x01\x00", ImageFormat::Ico),
+            (
+                b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf",
+                ImageFormat::Avif,
+            ),
+            (
+                b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00mif1heic",
+                ImageFormat::Heic,
+            ),
+            (
+                b"\xEF\xBB\xBF<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                ImageFormat::Svg,
+            ),
+            (b"  <svg></svg>", ImageFormat::Svg),
+        ];
+        for (contents, format) in cases {
+            assert_eq!(ImageFormat::from_contents(contents), Some(format));
+            assert_eq!(ImageFormat::from_mime(format.mime()), Some(format));
+        }
+
+        assert_eq!(ImageFormat::from_contents(b"hello"), None);
+        assert_eq!(
+            ImageFormat::from_contents(b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00isom"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_image() {
+        let jpeg = b"\xFF\xD8\xFF\xE0";
+        assert_eq!(check_image("x.JPG", jpeg).unwrap(), ImageFormat::Jpeg);
+        assert_eq!(check_image("x", jpeg).unwrap(), ImageFormat::Jpeg);
+        assert!(check_image("x.png", jpeg).is_err());
+        assert!(check_image("x.png", b"not an image").is_err());
     }
 
     #[test]
@@ -50,4 +302,14 @@ mod test {
         assert_eq!(FileType::Unknown, file_type("x"));
         assert_eq!(FileType::Unknown, file_type("x.weird_format"));
     }
+
+    #[test]
+    fn test_content_file_name() {
+        let name = content_file_name("Screen Shot 1.PNG", b"contents");
+        assert_eq!(name.len(), "0123456789abcdef.png".len());
+        assert!(name.ends_with(".png"));
+        assert_eq!(name, content_file_name("image.png", b"contents"));
+        assert_ne!(name, content_file_name("image.png", b"other contents"));
+        assert!(!content_file_name("image", b"contents").contains('.'));
+    }
 }
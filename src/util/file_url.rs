@@ -0,0 +1,314 @@
+//! Build the URLs of the uploaded files according to `UserConfig.url_style`.
+
+use crate::{
+    config::{BackendKind, UrlStyle, UserConfig},
+    util::template::expand,
+};
+use anyhow::{anyhow, Result};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::Url;
+
+/// Characters escaped in a path segment of the URLs
+const SEGMENT_ESCAPE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Where an uploaded file is in the Repo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    /// Branch that the file is uploaded to
+    pub branch: &'a str,
+    /// Path of the file in the Repo
+    pub path: &'a str,
+    /// SHA of a commit that contains the file
+    pub sha: &'a str,
+}
+
+/// Return the URL of the file at `location`.
+///
+/// | Style       | URL                                                   |
+/// | ----------- | ----------------------------------------------------- |
+/// | `blob`      | `{web_base_url}/{owner}/{repo}/blob/{branch}/{path}`  |
+/// | `raw`       | `{raw_base_url}/{owner}/{repo}/{branch}/{path}`       |
+/// | `jsdelivr`  | `https://cdn.jsdelivr.net/gh/{owner}/{repo}@{branch}/{path}` |
+/// | `pages`     | `https://{owner}.github.io/{repo}/{path}`              |
+/// | `permalink` | `{raw_base_url}/{owner}/{repo}/{sha}/{path}`          |
+/// | `template`  | `UserConfig.url_template`                             |
+///
+/// See [`UserConfig::web_base_url`] and [`UserConfig::raw_base_url`] for the
+/// base URLs.
+///
+/// # Error
+/// `url_template` is not set or can not be expanded, or the style is
+/// `jsdelivr` or `pages` and the Repo is not on github.com, these services
+/// only serve github.com repos.
+pub fn file_url(config: &UserConfig, location: Location<'_>) -> Result<String> {
+    let owner = config.github_user_name.as_str();
+    let repo = config.github_repo_name.as_str();
+    let path = encode_path(location.path);
+    let branch = encode_path(location.branch);
+    let web_base_url = config.web_base_url();
+    let raw_base_url = config.raw_base_url();
+
+    let url = match config.url_style {
+        UrlStyle::Jsdelivr | UrlStyle::Pages if !config.is_github_com() => {
+            return Err(anyhow!(
+                "`url_style = \"jsdelivr\"` and `\"pages\"` only work with github.com, set `url_template` for GitHub Enterprise Server"
+            ));
+        }
+        UrlStyle::Blob => format!(
+            "{}/{}/{}/blob/{}/{}",
+            web_base_url, owner, repo, branch, path
+        ),
+        UrlStyle::Raw => format!("{}/{}/{}/{}/{}", raw_base_url, owner, repo, branch, path),
+        UrlStyle::Jsdelivr => format!(
+            "https://cdn.jsdelivr.net/gh/{}/{}@{}/{}",
+            owner, repo, branch, path
+        ),
+        UrlStyle::Pages => format!("{}{}", pages_root(owner, repo), path),
+        UrlStyle::Permalink => format!(
+            "{}/{}/{}/{}/{}",
+            raw_base_url, owner, repo, location.sha, path
+        ),
+        UrlStyle::Template => {
+            let template = config
+                .url_template
+                .as_ref()
+                .ok_or_else(|| anyhow!("`url_style = \"template\"` requires `url_template`"))?;
+            expand(template, |variable| {
+                let value = match variable {
+                    "owner" => owner.to_string(),
+                    "repo" => repo.to_string(),
+                    "branch" => branch.clone(),
+                    "path" => path.clone(),
+                    "sha" => location.sha.to_string(),
+                    _ => return None,
+                };
+                Some(value)
+            })?
+        }
+    };
+
+    Ok(url)
+}
+
+/// Return true if `url` points at a file in the Repo, in any of the URL
+/// styles, or in the GitLab project, the Gitea repo or the S3 bucket if it is
+/// the backend.
+pub fn is_own_url(config: &UserConfig, url: &Url) -> bool {
+    let owner = config.github_user_name.as_str();
+    let repo = config.github_repo_name.as_str();
+    let mut prefixes = vec![
+        format!("{}/{}/{}/", config.web_base_url(), owner, repo),
+        format!("{}/{}/{}/", config.raw_base_url(), owner, repo),
+        format!("https://cdn.jsdelivr.net/gh/{}/{}@", owner, repo),
+        pages_root(owner, repo),
+    ];
+    // a GitLab project given by its path, its ID does not appear in the URLs
+    if config.backend == BackendKind::GitLab {
+        prefixes.push(format!(
+            "{}/{}/",
+            config.gitlab_url.trim_end_matches('/'),
+            config.gitlab_project()
+        ));
+    }
+    if config.backend == BackendKind::Gitea {
+        prefixes.push(format!(
+            "{}/{}/{}/",
+            config.gitea_url.trim_end_matches('/'),
+            owner,
+            repo
+        ));
+    }
+    if let (BackendKind::S3, Some(s3)) = (config.backend, &config.s3) {
+        match s3.url_template {
+            Some(ref template) => prefixes.extend(template_prefix(template)),
+            None => prefixes.push(format!("{}/{}/", s3.endpoint(), s3.bucket)),
+        }
+    }
+    if let Some(ref template) = config.url_template {
+        prefixes.extend(template_prefix(template));
+    }
+
+    // `http` and `https` are the same
+    let url = url.as_str().replacen("http://", "https://", 1);
+    prefixes.iter().any(|prefix| {
+        let prefix = prefix.replacen("http://", "https://", 1);
+        url.len() >= prefix.len()
+            && url.is_char_boundary(prefix.len())
+            && url[..prefix.len()].eq_ignore_ascii_case(&prefix)
+    })
+}
+
+/// The part of `template` before the first variable, `None` if it is empty.
+fn template_prefix(template: &str) -> Option<String> {
+    let prefix = &template[..template.find('{').unwrap_or(template.len())];
+    (!prefix.is_empty()).then(|| prefix.to_string())
+}
+
+/// The root URL of the GitHub Pages site of the Repo, ending with `/`.
+fn pages_root(owner: &str, repo: &str) -> String {
+    let host = format!("{}.github.io", owner.to_ascii_lowercase());
+    if repo.eq_ignore_ascii_case(&host) {
+        format!("https://{}/", host)
+    } else {
+        format!("https://{}/{}/", host, repo)
+    }
+}
+
+/// Percent-encode every segment of `path`, `/`s are kept.
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT_ESCAPE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test_config;
+
+    /// [`test_config`] of the Repo `User/pic`, the user name is not lowercase.
+    fn config(extra: &str) -> UserConfig {
+        let mut cfg = test_config(extra);
+        cfg.github_user_name = "User".to_string();
+        cfg.github_repo_name = "pic".to_string();
+        cfg
+    }
+
+    const LOCATION: Location<'static> = Location {
+        branch: "main",
+        path: "2024/a b.png",
+        sha: "0123abcd",
+    };
+
+    #[test]
+    fn test_file_url() {
+        let cases = [
+            ("", "https://github.com/User/pic/blob/main/2024/a%20b.png"),
+            (
+                "url_style = \"raw\"",
+                "https://raw.githubusercontent.com/User/pic/main/2024/a%20b.png",
+            ),
+            (
+                "url_style = \"jsdelivr\"",
+                "https://cdn.jsdelivr.net/gh/User/pic@main/2024/a%20b.png",
+            ),
+            (
+                "url_style = \"pages\"",
+                "https://user.github.io/pic/2024/a%20b.png",
+            ),
+            (
+                "url_style = \"permalink\"",
+                "https://raw.githubusercontent.com/User/pic/0123abcd/2024/a%20b.png",
+            ),
+            (
+                "url_style = \"template\"\nurl_template = \"https://img.example.com/{path}?v={sha}\"",
+                "https://img.example.com/2024/a%20b.png?v=0123abcd",
+            ),
+        ];
+        for (extra, url) in cases {
+            assert_eq!(file_url(&config(extra), LOCATION).unwrap(), url);
+        }
+
+        let mut cfg = config("url_style = \"pages\"");
+        cfg.github_repo_name = "user.github.io".to_string();
+        assert_eq!(
+            file_url(&cfg, LOCATION).unwrap(),
+            "https://user.github.io/2024/a%20b.png"
+        );
+
+        assert!(file_url(&config("url_style = \"template\""), LOCATION).is_err());
+    }
+
+    #[test]
+    fn test_is_own_url() {
+        let cfg = config("url_template = \"https://img.example.com/{path}\"");
+        let own = [
+            "https://github.com/user/pic/blob/main/a.png",
+            "https://raw.githubusercontent.com/User/pic/main/a.png",
+            "https://cdn.jsdelivr.net/gh/User/pic@main/a.png",
+            "http://user.github.io/pic/a.png",
+            "https://img.example.com/a.png",
+        ];
+        for url in own {
+            assert!(is_own_url(&cfg, &Url::parse(url).unwrap()), "{}", url);
+        }
+
+        let others = [
+            "https://github.com/user/pictures/blob/main/a.png",
+            "https://raw.githubusercontent.com/other/pic/main/a.png",
+            "https://example.com/a.png",
+        ];
+        for url in others {
+            assert!(!is_own_url(&cfg, &Url::parse(url).unwrap()), "{}", url);
+        }
+
+        let cfg = config(
+            "backend = \"s3\"\n[s3]\nendpoint = \"http://localhost:9000\"\nbucket = \"img\"\naccess_key_id = \"id\"",
+        );
+        assert!(is_own_url(
+            &cfg,
+            &Url::parse("http://localhost:9000/img/a.png").unwrap()
+        ));
+        assert!(!is_own_url(
+            &cfg,
+            &Url::parse("http://localhost:9000/other/a.png").unwrap()
+        ));
+
+        let cfg = config("backend = \"gitlab\"\ngitlab_project = \"group/pic\"");
+        assert!(is_own_url(
+            &cfg,
+            &Url::parse("https://gitlab.com/group/pic/-/raw/main/a.png").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_enterprise_url() {
+        let cfg = config("api_base_url = \"https://ghe.corp/api/v3/\"");
+        assert_eq!(
+            file_url(&cfg, LOCATION).unwrap(),
+            "https://ghe.corp/User/pic/blob/main/2024/a%20b.png"
+        );
+
+        for style in ["jsdelivr", "pages"] {
+            let cfg = config(&format!(
+                "api_base_url = \"https://ghe.corp/api/v3\"\nurl_style = \"{}\"",
+                style
+            ));
+            assert!(file_url(&cfg, LOCATION).is_err());
+        }
+
+        let cfg = config("api_base_url = \"https://ghe.corp/api/v3\"\nurl_style = \"raw\"");
+        assert_eq!(
+            file_url(&cfg, LOCATION).unwrap(),
+            "https://ghe.corp/raw/User/pic/main/2024/a%20b.png"
+        );
+        assert!(is_own_url(
+            &cfg,
+            &Url::parse("https://ghe.corp/User/pic/blob/main/a.png").unwrap()
+        ));
+        assert!(!is_own_url(
+            &cfg,
+            &Url::parse("https://github.com/User/pic/blob/main/a.png").unwrap()
+        ));
+
+        let cfg = config(
+            "api_base_url = \"http://localhost:8080\"\nraw_base_url = \"http://localhost:8081/\"\nurl_style = \"raw\"",
+        );
+        assert_eq!(
+            file_url(&cfg, LOCATION).unwrap(),
+            "http://localhost:8081/User/pic/main/2024/a%20b.png"
+        );
+        assert_eq!(cfg.web_base_url(), "http://localhost:8080");
+    }
+}
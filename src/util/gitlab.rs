@@ -0,0 +1,263 @@
+//! The GitLab backend, which commits the files to a GitLab project through the
+//! repository files API.
+//!
+//! The TOKEN is a GitLab personal access token with the `api` scope.
+
+use crate::{
+    config::UserConfig,
+    util::{
+        backend::{
+            commit_message, doc_name, fetch_json, send_json, upload_path, Backend, UploadedFile,
+        },
+        encode::encode,
+        file_url::encode_path,
+    },
+};
+use anyhow::{anyhow, Result};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    header::{HeaderMap, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{path::Path, sync::OnceLock};
+
+/// The GitLab backend, which uploads the files to the project.
+#[derive(Debug)]
+pub struct GitLab<'a> {
+    client: Client,
+    header: HeaderMap,
+    user_cfg: &'a UserConfig,
+    /// The project, fetched when it is needed for the first time
+    project: OnceLock<Project>,
+}
+
+/// A GitLab project
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct Project {
+    /// URL of the web page of the project
+    web_url: String,
+    /// Default branch of the project
+    default_branch: String,
+}
+
+/// Request body of the GitLab repository files API to create or delete a file
+#[derive(Debug, Serialize)]
+struct FileBody<'a> {
+    /// Branch to commit to
+    branch: &'a str,
+    /// Base64-encoded file contents, `None` for deletions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    /// Encoding of `content`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    /// Commit message
+    commit_message: String,
+    /// Author name of the commit, the authenticated user if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_name: Option<&'a str>,
+    /// Author email of the commit, the authenticated user if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_email: Option<&'a str>,
+}
+
+impl<'a> GitLab<'a> {
+    /// Initialize a [`GitLab`] backend.
+    pub fn init(user_cfg: &'a UserConfig) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.append("User-Agent", HeaderValue::from_static("pup"));
+        headers.append(
+            "PRIVATE-TOKEN",
+            HeaderValue::from_str(user_cfg.token.as_ref().unwrap())
+                .expect("failed to parse header value"),
+        );
+
+        Self {
+            client: Client::new(),
+            header: headers,
+            user_cfg,
+            project: OnceLock::new(),
+        }
+    }
+
+    /// The project, see [`UserConfig::gitlab_project`].
+    fn project(&self) -> Result<&Project> {
+        if let Some(project) = self.project.get() {
+            return Ok(project);
+        }
+
+        let body = self.send(self.client.get(project_api(self.user_cfg, "")))?;
+        let project =
+            serde_json::from_value(body).map_err(|_| anyhow!("unexpected response from GitLab"))?;
+        Ok(self.project.get_or_init(|| project))
+    }
+
+    /// The configured branch, or the default branch of the project.
+    fn branch(&self) -> Result<String> {
+        match self.user_cfg.branch {
+            Some(ref branch) => Ok(branch.clone()),
+            None => Ok(self.project()?.default_branch.clone()),
+        }
+    }
+
+    /// Fetch the file at `path` on `branch`, return `None` if it does not
+    /// exist.
+    fn existing_file(&self, path: &str, branch: &str) -> Result<Option<Value>> {
+        let req = self
+            .client
+            .get(file_api(self.user_cfg, path))
+            .query(&[("ref", branch)]);
+        fetch_json(req, &self.header)
+    }
+
+    /// Send `req` with the GitLab headers, return the JSON response body.
+    ///
+    /// # Error
+    /// The request fails or the response status is not successful.
+    fn send(&self, req: RequestBuilder) -> Result<Value> {
+        send_json(req, &self.header)
+    }
+}
+
+impl Backend for GitLab<'_> {
+    /// Commit `contents` to the project as file `file_name`, return its raw
+    /// file URL, see [`raw_url`].
+    ///
+    /// An existing file is compared by its `content_sha256`.
+    fn upload(&self, file_name: &str, contents: &[u8], doc: Option<&Path>) -> Result<UploadedFile> {
+        let user_cfg = self.user_cfg;
+        let path = upload_path(file_name, contents, doc, user_cfg)?;
+        let branch = self.branch()?;
+        let author = user_cfg.author.as_ref();
+
+        let body = FileBody {
+            branch: &branch,
+            content: Some(String::from_utf8(encode(contents)?)?),
+            encoding: Some("base64"),
+            commit_message: commit_message(&path, &doc_name(doc), user_cfg)?,
+            author_name: author.map(|author| author.name.as_str()),
+            author_email: author.map(|author| author.email.as_str()),
+        };
+        let created = self.send(self.client.post(file_api(user_cfg, &path)).json(&body));
+
+        if let Err(msg) = created {
+            let sha256 = format!("{:x}", Sha256::digest(contents));
+            let identical = self
+                .existing_file(&path, &branch)?
+                .is_some_and(|existing| existing["content_sha256"].as_str() == Some(&sha256));
+            if !identical {
+                return Err(msg);
+            }
+        }
+
+        Ok(UploadedFile {
+            url: raw_url(&self.project()?.web_url, &branch, &path),
+            path,
+        })
+    }
+
+    /// Return true if the file at `path` exists on the branch.
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.existing_file(path, &self.branch()?)?.is_some())
+    }
+
+    /// Delete the file at `path` from the branch in a new commit.
+    fn delete(&self, path: &str) -> Result<()> {
+        let author = self.user_cfg.author.as_ref();
+        let body = FileBody {
+            branch: &self.branch()?,
+            content: None,
+            encoding: None,
+            commit_message: format!("delete {}", path),
+            author_name: author.map(|author| author.name.as_str()),
+            author_email: author.map(|author| author.email.as_str()),
+        };
+        self.send(
+            self.client
+                .delete(file_api(self.user_cfg, path))
+                .json(&body),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// URL of the GitLab API endpoint `path` of the project, i.e.,
+/// `{gitlab_url}/api/v4/projects/{project}/{path}`, the project is URL-encoded.
+fn project_api(user_cfg: &UserConfig, path: &str) -> String {
+    let project = user_cfg.gitlab_project();
+    let url = format!(
+        "{}/api/v4/projects/{}/{}",
+        user_cfg.gitlab_url.trim_end_matches('/'),
+        utf8_percent_encode(&project, NON_ALPHANUMERIC),
+        path
+    );
+    url.trim_end_matches('/').to_string()
+}
+
+/// URL of the file at `path` in the repository files API, the path is
+/// URL-encoded, including the `/`s.
+fn file_api(user_cfg: &UserConfig, path: &str) -> String {
+    let path = utf8_percent_encode(path, NON_ALPHANUMERIC).to_string();
+    project_api(user_cfg, &format!("repository/files/{}", path))
+}
+
+/// Raw file URL of the file at `path` on `branch` of the project whose web
+/// page is `web_url`, i.e., `{web_url}/-/raw/{branch}/{path}`.
+fn raw_url(web_url: &str, branch: &str, path: &str) -> String {
+    format!(
+        "{}/-/raw/{}/{}",
+        web_url.trim_end_matches('/'),
+        encode_path(branch),
+        encode_path(path)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_file_api() {
+        assert_eq!(
+            file_api(&test_config(""), "2024/a b.png"),
+            "https://gitlab.com/api/v4/projects/user%2Frepo/repository/files/2024%2Fa%20b%2Epng"
+        );
+
+        let cfg = test_config("gitlab_url = \"https://gitlab.corp/\"\ngitlab_project = \"42\"");
+        assert_eq!(
+            project_api(&cfg, ""),
+            "https://gitlab.corp/api/v4/projects/42"
+        );
+    }
+
+    #[test]
+    fn test_raw_url() {
+        assert_eq!(
+            raw_url("https://gitlab.com/group/sub/repo", "main", "2024/a b.png"),
+            "https://gitlab.com/group/sub/repo/-/raw/main/2024/a%20b.png"
+        );
+    }
+
+    #[test]
+    fn test_file_body() {
+        let cfg = test_config("author = { name = \"A\", email = \"a@b.c\" }");
+        let author = cfg.author.as_ref();
+        let body = FileBody {
+            branch: "main",
+            content: Some("aGk=".to_string()),
+            encoding: Some("base64"),
+            commit_message: "upload a.png".to_string(),
+            author_name: author.map(|author| author.name.as_str()),
+            author_email: author.map(|author| author.email.as_str()),
+        };
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"branch":"main","content":"aGk=","encoding":"base64","commit_message":"upload a.png","author_name":"A","author_email":"a@b.c"}"#
+        );
+    }
+}
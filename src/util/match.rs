@@ -1,57 +1,562 @@
 //! Offers `![](/)` match functionality in markdown manipulation
+//!
+//! The document is parsed by a CommonMark (with GFM extensions) parser, so
+//! text that merely looks like an image, e.g., the ones in fenced code blocks,
+//! code spans or HTML comments, won't be matched.
+//!
+//! Raw HTML `<img>` and `<source>` tags (e.g., the ones in a `<picture>`) are
+//! matched as well. The Obsidian wiki embeds, i.e., `![[xxx.png|400]]`, are
+//! only matched in a vault, see [`find_all`].
+//!
+//! The destinations of regular links, i.e., `[slides](./deck.pdf)`, can be
+//! matched by [`find_all`].
 
+use crate::util::file_type::{file_type, FileType};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
 use regex::Regex;
-use std::ops::{Index, Range};
+use std::{cmp::Reverse, ops::Range, path::PathBuf, sync::LazyLock};
 
-/// type to represent a matched line
-pub struct MatchedLine<'lifetime_of_line> {
-    /// Line.
-    pub line: &'lifetime_of_line mut String,
-    /// The position of the path on a line.
+/// Characters that need to be percent-encoded in a markdown link destination.
+const MD_ESCAPE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'<')
+    .add(b'>')
+    .add(b'(')
+    .add(b')')
+    .add(b'\\');
+
+/// Characters that need to be percent-encoded in a HTML attribute value.
+const HTML_ESCAPE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`')
+    .add(b',');
+
+/// Used to match the `<img>` and `<source>` tags, HTML comments are matched
+/// so that the tags in them can be skipped. A `>` in a quoted attribute value
+/// does not end the tag.
+static HTML_TAG_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<!--.*?-->|<(?:img|source)\b(?:[^>"']|"[^"]*"|'[^']*')*>"#).unwrap()
+});
+
+/// Used to match the `src` and `srcset` attributes of a tag, the value is
+/// captured in group 2, 3 or 4 depending on how it is quoted.
+static HTML_ATTR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\s(src|srcset)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+))"#).unwrap()
+});
+
+/// Where a destination is found, which decides how it is escaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationKind {
+    /// Markdown inline image or link reference definition
+    Markdown,
+    /// Attribute of a raw HTML tag
+    Html,
+    /// Obsidian wiki embed, which will be rewritten to a standard markdown
+    /// image, `alt` is the file name followed by the size hint if any, e.g.,
+    /// `diagram.png|400`.
+    WikiEmbed { alt: String },
+}
+
+/// type to represent a matched image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedImage {
+    /// The position of the image destination in the document.
     ///
     /// For example:
     /// "This is a pic ![pic](pic.jpeg)!"
-    /// then `range` will be `Range {start: 21, end: 28 }`.
+    /// then `range` will be `Range {start: 21, end: 29 }`.
+    ///
+    /// The angle brackets of a `<...>` destination are included, the title is
+    /// not. For a wiki embed, this is the range of the whole embed.
     pub range: Range<usize>,
+    /// The kind of this destination.
+    pub kind: DestinationKind,
+    /// The destination with the angle brackets, backslash escapes and entity
+    /// references decoded, but NOT percent-decoded.
+    pub dest: String,
+    /// Set if this is the destination of a regular link rather than an image.
+    pub link: Option<MatchedLink>,
 }
 
-impl<'lifetime_of_line> MatchedLine<'lifetime_of_line> {
-    /// Check and initialize a matched line struct
+/// type to represent the link of a matched link destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedLink {
+    /// The position of the whole inline link, i.e., `[text](dest "title")`,
+    /// `None` for a link reference definition.
+    pub span: Option<Range<usize>>,
+    /// The link text as written, or the label for a link reference
+    /// definition.
+    pub text: String,
+}
+
+impl MatchedImage {
+    /// The local path this destination refers to, e.g., `my%20file.png` refers
+    /// to `my file.png`.
     ///
-    /// Return `Ok(MatchedLine<'_>)` if `line` contains a picture entry.
-    pub fn new(line: &'lifetime_of_line mut String) -> Option<Self> {
-        // used to match the markdown image path
-        let image_path_re = Regex::new(r#"!\[.*\]\(.*\)"#).unwrap();
+    /// Wiki embeds are not percent-encoded.
+    pub fn path(&self) -> PathBuf {
+        match self.kind {
+            DestinationKind::WikiEmbed { .. } => PathBuf::from(&self.dest),
+            _ => PathBuf::from(percent_decode_str(&self.dest).decode_utf8_lossy().as_ref()),
+        }
+    }
 
-        if image_path_re.is_match(line) {
-            let image_path_match = image_path_re.find(line).unwrap();
+    /// Escape `url` so that it can be put at `range`.
+    pub fn escape(&self, url: &str) -> String {
+        match self.kind {
+            DestinationKind::Markdown => utf8_percent_encode(url, MD_ESCAPE_SET).to_string(),
+            DestinationKind::Html => html_escape(url),
+            DestinationKind::WikiEmbed { ref alt } => {
+                let alt = alt
+                    .replace('\\', "\\\\")
+                    .replace('[', "\\[")
+                    .replace(']', "\\]");
+                format!("![{}]({})", alt, utf8_percent_encode(url, MD_ESCAPE_SET))
+            }
+        }
+    }
 
-            // used to match the parenthesis
-            let parenthesis_re = Regex::new(r#"\(.*\)"#).unwrap();
-            let image_path = line.index(image_path_match.range());
-            let parenthesis_mth = parenthesis_re.find(image_path).unwrap();
+    /// Replace the whole inline link with a `<video>` tag playing `url`, the
+    /// link text is used as the fallback content.
+    ///
+    /// Return the range to replace and the tag, or `None` if this is not an
+    /// inline link.
+    pub fn video_tag(&self, url: &str) -> Option<(Range<usize>, String)> {
+        let link = self.link.as_ref()?;
+        let tag = format!(
+            "<video src=\"{}\" controls>{}</video>",
+            html_escape(url),
+            link.text
+        );
 
-            // calculate the range`[start, end)` of path in this line
-            let start = parenthesis_mth.start() + image_path_match.start() + 1; // inclusive
-            let end = parenthesis_mth.end() + image_path_match.start() - 1; // exclusive
+        Some((link.span.clone()?, tag))
+    }
+}
+
+/// Escape `url` so that it can be used as a HTML attribute value.
+fn html_escape(url: &str) -> String {
+    utf8_percent_encode(url, HTML_ESCAPE_SET)
+        .to_string()
+        .replace('&', "&amp;")
+}
 
-            if line[start..end].starts_with("https://") || line[start..end].is_empty() {
-                None
+/// Parser options, CommonMark plus the GFM extensions, and the wiki links if
+/// `wiki_links` is set.
+fn parser_options(wiki_links: bool) -> Options {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    if wiki_links {
+        options | Options::ENABLE_WIKILINKS
+    } else {
+        options
+    }
+}
+
+/// Find all the images that need to be uploaded in `doc`.
+///
+/// For reference-style images, i.e., `![alt][label]`, the destination of the
+/// link reference definition used by them is matched.
+///
+/// Images whose destination is empty are skipped, use
+/// [`dest_type`](crate::util::dest_type::dest_type) to find out which of them
+/// are local files. Wiki embeds are not matched.
+pub fn find_images(doc: &str) -> Vec<MatchedImage> {
+    find_all(doc, false)
+        .into_iter()
+        .filter(|mth| mth.link.is_none())
+        .collect()
+}
+
+/// Find all the images and the regular links in `doc`, the links have
+/// [`MatchedImage::link`] set.
+///
+/// A link reference definition used by both images and links is matched as
+/// an image.
+///
+/// Wiki embeds are matched if `wiki_embeds` is set, which should only be done
+/// in an Obsidian vault: `![[1]](fig.png)` is a CommonMark image whose alt
+/// text is `[1]`, but a wiki embed followed by `(fig.png)` in a vault.
+pub fn find_all(doc: &str, wiki_embeds: bool) -> Vec<MatchedImage> {
+    let mut images = Vec::new();
+    let mut image_labels = Vec::new();
+    let mut link_labels = Vec::new();
+    // the links being parsed, with the end of their text known so far, and
+    // their destinations if they are inline links
+    let mut open_links = Vec::new();
+    let mut events = Parser::new_ext(doc, parser_options(wiki_embeds)).into_offset_iter();
+
+    while let Some((event, span)) = events.next() {
+        if let Some((_, text_end, _)) = open_links.last_mut() {
+            if !matches!(event, Event::End(TagEnd::Link)) {
+                *text_end = span.end.max(*text_end);
+            }
+        }
+
+        match event {
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                id,
+                ..
+            }) => {
+                // The alt text ends where the last event nested in this image ends,
+                // an empty alt text ends right after "![".
+                let mut alt_end = span.start + 2;
+                let mut depth = 0_usize;
+                for (event, range) in events.by_ref() {
+                    match event {
+                        Event::End(TagEnd::Image) if depth == 0 => break,
+                        Event::End(TagEnd::Image) => depth -= 1,
+                        Event::Start(Tag::Image { .. }) => depth += 1,
+                        _ => {}
+                    }
+                    alt_end = alt_end.max(range.end);
+                }
+
+                match link_type {
+                    LinkType::Inline => {
+                        if let Some(range) = inline_destination(doc, alt_end..span.end) {
+                            push_image(
+                                &mut images,
+                                range,
+                                DestinationKind::Markdown,
+                                &dest_url,
+                                None,
+                            );
+                        }
+                    }
+                    LinkType::Reference | LinkType::Collapsed | LinkType::Shortcut => {
+                        image_labels.push(id);
+                    }
+                    // embeds of other notes are not images, and an embed followed
+                    // by `(` is likely meant to be a CommonMark image, leave it
+                    // alone rather than breaking it
+                    LinkType::WikiLink { .. } => {
+                        let (target, alt) = wiki_embed(&doc[span.clone()]);
+                        if file_type(target) != FileType::Image
+                            || doc.as_bytes().get(span.end) == Some(&b'(')
+                        {
+                            continue;
+                        }
+                        push_image(
+                            &mut images,
+                            span,
+                            DestinationKind::WikiEmbed { alt },
+                            target,
+                            None,
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                id,
+                ..
+            }) => {
+                let dest = match link_type {
+                    LinkType::Inline => Some(dest_url),
+                    LinkType::Reference | LinkType::Collapsed | LinkType::Shortcut => {
+                        link_labels.push(id);
+                        None
+                    }
+                    _ => None,
+                };
+                open_links.push((span.clone(), span.start + 1, dest));
+            }
+            Event::End(TagEnd::Link) => {
+                if let Some((span, text_end, Some(dest))) = open_links.pop() {
+                    if let Some(range) = inline_destination(doc, text_end..span.end) {
+                        let link = MatchedLink {
+                            text: doc[span.start + 1..text_end].to_string(),
+                            span: Some(span),
+                        };
+                        push_image(
+                            &mut images,
+                            range,
+                            DestinationKind::Markdown,
+                            &dest,
+                            Some(link),
+                        );
+                    }
+                }
+            }
+            Event::Start(Tag::HtmlBlock) | Event::InlineHtml(_) => {
+                for range in html_destinations(doc, span) {
+                    let dest = html_unescape(&doc[range.clone()]);
+                    push_image(&mut images, range, DestinationKind::Html, &dest, None);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // a definition can be used by multiple images and links, match it only
+    // once, and as an image if it is used by any image
+    let mut definitions = image_labels
+        .iter()
+        .map(|label| (label, false))
+        .chain(link_labels.iter().map(|label| (label, true)))
+        .filter_map(|(label, is_link)| {
+            events
+                .reference_definitions()
+                .get(label)
+                .map(|definition| (label, definition, is_link))
+        })
+        .collect::<Vec<_>>();
+    definitions.sort_by_key(|(_, definition, is_link)| (definition.span.start, *is_link));
+    definitions.dedup_by_key(|(_, definition, _)| definition.span.start);
+    for (label, definition, is_link) in definitions {
+        if let Some(range) = definition_destination(doc, definition.span.clone()) {
+            let link = is_link.then(|| MatchedLink {
+                span: None,
+                text: label.to_string(),
+            });
+            push_image(
+                &mut images,
+                range,
+                DestinationKind::Markdown,
+                &definition.dest,
+                link,
+            );
+        }
+    }
+
+    images
+}
+
+/// Push the destination at `range` to `images` if it is not empty.
+fn push_image(
+    images: &mut Vec<MatchedImage>,
+    range: Range<usize>,
+    kind: DestinationKind,
+    dest: &str,
+    link: Option<MatchedLink>,
+) {
+    if !dest.is_empty() {
+        images.push(MatchedImage {
+            range,
+            kind,
+            dest: dest.to_string(),
+            link,
+        });
+    }
+}
+
+/// Split a wiki embed, i.e., `![[img/diagram.png|400]]`, into its target and
+/// the alt text of the standard markdown image it is rewritten to, e.g.,
+/// `img/diagram.png` and `diagram.png|400`.
+///
+/// The heading or block reference (`#...`) of the target is removed.
+fn wiki_embed(embed: &str) -> (&str, String) {
+    let inner = embed
+        .trim_start_matches("![[")
+        .trim_end_matches("]]")
+        .trim();
+    let (target, hint) = match inner.split_once('|') {
+        Some((target, hint)) => (target, Some(hint.trim())),
+        None => (inner, None),
+    };
+    let target = target.split('#').next().unwrap_or_default().trim();
+    let name = target.rsplit('/').next().unwrap_or_default();
+
+    let alt = match hint {
+        Some(hint) => format!("{}|{}", name, hint),
+        None => name.to_string(),
+    };
+
+    (target, alt)
+}
+
+/// Decode the entity references that commonly appear in a HTML attribute
+/// value.
+fn html_unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Locate the destination of an inline image, `span` should start at the end
+/// of the alt text and end at the end of the image.
+///
+/// Return `None` if the destination is empty.
+fn inline_destination(doc: &str, span: Range<usize>) -> Option<Range<usize>> {
+    let open = span.start + doc[span.clone()].find("](")? + 2;
+    destination(doc, open..span.end)
+}
+
+/// Locate the destination of a link reference definition, i.e.,
+/// `[label]: destination "title"`, `span` is the span of the definition.
+///
+/// Return `None` if the destination is empty.
+fn definition_destination(doc: &str, span: Range<usize>) -> Option<Range<usize>> {
+    let bytes = doc.as_bytes();
+
+    // the label ends at the first unescaped `]`
+    let mut colon = span.start + 1;
+    while colon < span.end && bytes[colon] != b']' {
+        colon += if bytes[colon] == b'\\' { 2 } else { 1 };
+    }
+    if bytes.get(colon + 1) != Some(&b':') {
+        return None;
+    }
+
+    destination(doc, colon + 2..span.end)
+}
+
+/// Locate the `src` of the `<img>`/`<source>` tags and every URL of their
+/// `srcset` in the raw HTML at `span`.
+fn html_destinations(doc: &str, span: Range<usize>) -> Vec<Range<usize>> {
+    let mut destinations = Vec::new();
+
+    for tag in HTML_TAG_RE.find_iter(&doc[span.clone()]) {
+        if tag.as_str().starts_with("<!--") {
+            continue;
+        }
+
+        let tag_start = span.start + tag.start();
+        for attr in HTML_ATTR_RE.captures_iter(tag.as_str()) {
+            let value = attr
+                .get(2)
+                .or_else(|| attr.get(3))
+                .or_else(|| attr.get(4))
+                .unwrap();
+            let value_start = tag_start + value.start();
+
+            if attr[1].eq_ignore_ascii_case("src") {
+                let src = value.as_str().trim();
+                if !src.is_empty() {
+                    let start = value_start + value.as_str().find(src).unwrap();
+                    destinations.push(start..start + src.len());
+                }
             } else {
-                Some(Self {
-                    line,
-                    range: Range { start, end },
-                })
+                destinations.extend(
+                    srcset_urls(value.as_str())
+                        .into_iter()
+                        .map(|range| value_start + range.start..value_start + range.end),
+                );
             }
-        } else {
-            None
         }
     }
 
-    /// Replace the local path with the returned URL
-    pub fn replace(&'lifetime_of_line mut self, url: &str) {
-        self.line.replace_range(self.range.clone(), url);
+    destinations
+}
+
+/// Locate the URL of every image candidate in a `srcset` attribute, e.g.,
+/// `"a.png 1x, b.png 2x"`.
+fn srcset_urls(srcset: &str) -> Vec<Range<usize>> {
+    let bytes = srcset.as_bytes();
+    let mut urls = Vec::new();
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        // skip the separators before an URL
+        while idx < bytes.len() && (bytes[idx].is_ascii_whitespace() || bytes[idx] == b',') {
+            idx += 1;
+        }
+        let start = idx;
+        while idx < bytes.len() && !bytes[idx].is_ascii_whitespace() {
+            idx += 1;
+        }
+        // an URL ending with commas is not followed by any descriptor
+        let mut end = idx;
+        while end > start && bytes[end - 1] == b',' {
+            end -= 1;
+        }
+        if start < end {
+            urls.push(start..end);
+        }
+        // skip the descriptors
+        if end == idx {
+            while idx < bytes.len() && bytes[idx] != b',' {
+                idx += 1;
+            }
+        }
     }
+
+    urls
+}
+
+/// Locate the link destination that follows the optional whitespace at the
+/// beginning of `span`.
+///
+/// Return `None` if the destination is empty.
+fn destination(doc: &str, span: Range<usize>) -> Option<Range<usize>> {
+    let bytes = &doc.as_bytes()[..span.end];
+
+    // skip the optional whitespace before the destination
+    let mut start = span.start;
+    while start < bytes.len() && bytes[start].is_ascii_whitespace() {
+        start += 1;
+    }
+
+    let mut end = start;
+    if bytes.get(start) == Some(&b'<') {
+        // `<...>` destination, ends at the first unescaped `>`
+        end += 1;
+        while end < bytes.len() && bytes[end] != b'>' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        end += 1;
+    } else {
+        // raw destination, ends at whitespace or the unbalanced `)`
+        let mut depth = 0_usize;
+        while end < bytes.len() {
+            match bytes[end] {
+                b'\\' => end += 1,
+                b'(' => depth += 1,
+                b')' if depth == 0 => break,
+                b')' => depth -= 1,
+                b if b.is_ascii_whitespace() || b.is_ascii_control() => break,
+                _ => {}
+            }
+            end += 1;
+        }
+    }
+
+    let end = end.min(bytes.len());
+    if start < end {
+        Some(start..end)
+    } else {
+        None
+    }
+}
+
+/// Replace each range of `doc` with the corresponding new contents, the rest
+/// of the document is kept untouched.
+///
+/// A replacement overlapping a previous one is skipped, so a replacement
+/// nested in another one, e.g., an image inside a link replaced by a
+/// `<video>` tag, is dropped in favor of the outer one.
+pub fn replace(doc: &str, mut replacements: Vec<(Range<usize>, String)>) -> String {
+    // the outer one comes first if they start at the same position
+    replacements.sort_by_key(|(range, _)| (range.start, Reverse(range.end)));
+
+    let mut new_doc = String::with_capacity(doc.len());
+    let mut last = 0;
+    for (range, contents) in replacements {
+        if range.start < last {
+            continue;
+        }
+        new_doc.push_str(&doc[last..range.start]);
+        new_doc.push_str(&contents);
+        last = range.end;
+    }
+    new_doc.push_str(&doc[last..]);
+
+    new_doc
 }
 
 #[cfg(test)]
@@ -59,37 +564,292 @@ mod test {
     use super::*;
     #[test]
     fn replace_test() {
-        let mut local_path: String = String::from("> ![title](/home/steve/doc.png)xx");
-        let mut mth: MatchedLine = MatchedLine::new(&mut local_path).unwrap();
+        let doc = "> ![title](/home/steve/doc.png)xx";
+        let mth = find_images(doc).pop().unwrap();
         let target_url = "https://github.com/SteveLauC/pic/blob/main/Screen%20Shot%202022-04-06%20at%2010.30.49%20AM.png";
-        mth.replace(target_url);
-        assert_eq!(local_path, "> ![title](https://github.com/SteveLauC/pic/blob/main/Screen%20Shot%202022-04-06%20at%2010.30.49%20AM.png)xx");
+        let new_doc = replace(doc, vec![(mth.range, target_url.into())]);
+        assert_eq!(new_doc, "> ![title](https://github.com/SteveLauC/pic/blob/main/Screen%20Shot%202022-04-06%20at%2010.30.49%20AM.png)xx");
     }
 
     #[test]
     fn matched_line_init_test() {
-        let mut line: String = String::from("> ![title](/home/steve/doc.png)xx");
-        let mth: MatchedLine = MatchedLine::new(&mut line).unwrap();
+        let mth = find_images("> ![title](/home/steve/doc.png)xx");
 
-        assert_eq!(mth.range, Range { start: 11, end: 30 });
+        assert_eq!(mth[0].range, Range { start: 11, end: 30 });
     }
 
     #[test]
     fn matched_test() {
         // empty path
-        let mut line1: String = "![]()".into();
-        assert!(MatchedLine::new(&mut line1).is_none());
+        assert!(find_images("![]()").is_empty());
 
-        let mut line2: String = "![](/)".into();
-        assert!(MatchedLine::new(&mut line2).is_some());
+        assert_eq!(find_images("![](/)").len(), 1);
 
-        let mut line3: String = "![aaa[()".into();
-        assert!(MatchedLine::new(&mut line3).is_none());
+        assert!(find_images("![aaa[()").is_empty());
         // url
-        let mut line4: String = "> 我们不是![ppt](https://.....)xxxx这样".into();
-        assert!(MatchedLine::new(&mut line4).is_none());
+        assert_eq!(
+            find_images("> 我们不是![ppt](https://.....)xxxx这样")[0].dest,
+            "https://....."
+        );
         // relative path
-        let mut line5: String = "![issustration](pic.png)".into();
-        assert!(MatchedLine::new(&mut line5).is_some());
+        assert_eq!(find_images("![issustration](pic.png)").len(), 1);
+    }
+
+    #[test]
+    fn not_image_test() {
+        let doc = "```\n![a](a.png)\n```\n`![b](b.png)`\n<!-- ![c](c.png) -->\n";
+        assert!(find_images(doc).is_empty());
+
+        let doc = "    ![indented](code.png)\n";
+        assert!(find_images(doc).is_empty());
+    }
+
+    #[test]
+    fn exact_range_test() {
+        let doc = "![a [nested] alt](dir/(1).png)";
+        let mth = find_images(doc);
+        assert_eq!(&doc[mth[0].range.clone()], "dir/(1).png");
+
+        let doc = "![a](a.png \"title\")";
+        let mth = find_images(doc);
+        assert_eq!(&doc[mth[0].range.clone()], "a.png");
+
+        let doc = "[![badge](badge.png)](https://example.com)";
+        let mth = find_images(doc);
+        assert_eq!(&doc[mth[0].range.clone()], "badge.png");
+    }
+
+    #[test]
+    fn multiple_images_per_line_test() {
+        let doc = "![a](a.png) ![b](b.png)\n| ![c](c.png) | ![d](d.png) |\n| - | - |\n";
+        let dests = find_images(doc)
+            .into_iter()
+            .map(|mth| &doc[mth.range])
+            .collect::<Vec<_>>();
+        assert_eq!(dests, ["a.png", "b.png", "c.png", "d.png"]);
+
+        let mths = find_images(doc);
+        let new_doc = replace(
+            doc,
+            mths.into_iter()
+                .map(|mth| (mth.range, "URL".to_string()))
+                .collect(),
+        );
+        assert_eq!(
+            new_doc,
+            "![a](URL) ![b](URL)\n| ![c](URL) | ![d](URL) |\n| - | - |\n"
+        );
+    }
+
+    #[test]
+    fn reference_test() {
+        let doc = "![diagram][arch] ![Arch][] ![arch]\n\n[arch]: ./img/arch.png \"Architecture\"\n";
+        let mths = find_images(doc);
+        assert_eq!(mths.len(), 1);
+        assert_eq!(&doc[mths[0].range.clone()], "./img/arch.png");
+
+        let new_doc = replace(doc, vec![(mths[0].range.clone(), "URL".into())]);
+        assert_eq!(
+            new_doc,
+            "![diagram][arch] ![Arch][] ![arch]\n\n[arch]: URL \"Architecture\"\n"
+        );
+
+        // definitions that are only used by links are left alone
+        let doc = "[link][a]\n\n[a]: ./a.png\n";
+        assert!(find_images(doc).is_empty());
+
+        let doc = "![x][a\\]b]\n\n[a\\]b]:\n  <./my img.png>\n";
+        let mths = find_images(doc);
+        assert_eq!(&doc[mths[0].range.clone()], "<./my img.png>");
+    }
+
+    #[test]
+    fn html_test() {
+        let doc = "<p align=\"center\">\n  <img src=\"x.png\" width=\"300\">\n</p>\n\ntext <IMG\n alt='y' SRC='y.png'/> text\n";
+        let dests = find_images(doc)
+            .into_iter()
+            .map(|mth| &doc[mth.range])
+            .collect::<Vec<_>>();
+        assert_eq!(dests, ["x.png", "y.png"]);
+
+        // `>` in a quoted attribute value
+        let doc = "<img alt=\"x>y\" src=\"a.png\"> <img alt='>' src=b.png>\n";
+        let dests = find_images(doc)
+            .into_iter()
+            .map(|mth| &doc[mth.range])
+            .collect::<Vec<_>>();
+        assert_eq!(dests, ["a.png", "b.png"]);
+
+        let doc = "<picture>\n<source srcset=\"a.webp 1x, b.webp 2x\" media=\"(min-width: 600px)\">\n<img src=c.png data-src=d.png>\n</picture>\n";
+        let mths = find_images(doc);
+        let new_doc = replace(
+            doc,
+            mths.into_iter()
+                .map(|mth| (mth.range.clone(), format!("URL-{}", &doc[mth.range])))
+                .collect(),
+        );
+        assert_eq!(new_doc, "<picture>\n<source srcset=\"URL-a.webp 1x, URL-b.webp 2x\" media=\"(min-width: 600px)\">\n<img src=URL-c.png data-src=d.png>\n</picture>\n");
+
+        // comments and code
+        let doc = "<!-- <img src=\"a.png\"> -->\n\n`<img src=\"b.png\">`\n\n<div>\n<!--\n<img src=\"c.png\">\n-->\n</div>\n";
+        assert!(find_images(doc).is_empty());
+    }
+
+    #[test]
+    fn srcset_test() {
+        let srcset = " a.png 1x,b.png 2x ,  c,d.png 3x,";
+        let urls = srcset_urls(srcset)
+            .into_iter()
+            .map(|range| &srcset[range])
+            .collect::<Vec<_>>();
+        assert_eq!(urls, ["a.png", "b.png", "c,d.png"]);
+    }
+
+    #[test]
+    fn title_and_angle_bracket_test() {
+        let doc =
+            "![alt](path/to/a.png \"Caption\") ![alt](<my file.png> 'x') ![](my%20file\\(1\\).png)";
+        let mths = find_images(doc);
+        let dests = mths.iter().map(|mth| mth.dest.as_str()).collect::<Vec<_>>();
+        assert_eq!(dests, ["path/to/a.png", "my file.png", "my%20file(1).png"]);
+        let paths = mths.iter().map(|mth| mth.path()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("path/to/a.png"),
+                PathBuf::from("my file.png"),
+                PathBuf::from("my file(1).png")
+            ]
+        );
+
+        let new_doc = replace(
+            doc,
+            mths.iter()
+                .map(|mth| (mth.range.clone(), mth.escape("https://x/a b(1).png")))
+                .collect(),
+        );
+        assert_eq!(new_doc, "![alt](https://x/a%20b%281%29.png \"Caption\") ![alt](https://x/a%20b%281%29.png 'x') ![](https://x/a%20b%281%29.png)");
+
+        // `<>` is an empty destination
+        assert!(find_images("![](<>)").is_empty());
+    }
+
+    #[test]
+    fn html_escape_test() {
+        let doc = "<img src=\"a&amp;b.png\">";
+        let mth = find_images(doc).pop().unwrap();
+        assert_eq!(mth.dest, "a&b.png");
+        assert_eq!(
+            mth.escape("https://x/a b.png?x=1&y=\"2\""),
+            "https://x/a%20b.png?x=1&amp;y=%222%22"
+        );
+    }
+
+    #[test]
+    fn wiki_embed_test() {
+        let doc = "![[screenshot.png]] and ![[img/diagram [1].png | 400x300]] ![[Other note]]\n";
+        assert!(find_images(doc).is_empty());
+        let mths = find_all(doc, true);
+        let dests = mths.iter().map(|mth| mth.dest.as_str()).collect::<Vec<_>>();
+        assert_eq!(dests, ["screenshot.png", "img/diagram [1].png"]);
+        assert_eq!(mths[1].path(), PathBuf::from("img/diagram [1].png"));
+
+        let new_doc = replace(
+            doc,
+            mths.iter()
+                .map(|mth| (mth.range.clone(), mth.escape("https://x/a b.png")))
+                .collect(),
+        );
+        assert_eq!(
+            new_doc,
+            "![screenshot.png](https://x/a%20b.png) and ![diagram \\[1\\].png|400x300](https://x/a%20b.png) ![[Other note]]\n"
+        );
+    }
+
+    #[test]
+    fn bracket_alt_test() {
+        // CommonMark images whose alt text starts with `[`
+        for doc in ["![[1]](fig.png)", "![[shot.png]](real.png)"] {
+            let dests = find_images(doc)
+                .into_iter()
+                .map(|mth| mth.dest)
+                .collect::<Vec<_>>();
+            assert_eq!(dests.len(), 1, "{}", doc);
+            assert_ne!(dests[0], "shot.png");
+        }
+        assert_eq!(find_images("![[1]](fig.png)")[0].dest, "fig.png");
+
+        // in a vault, they are wiki embeds followed by `(`, which are skipped
+        assert!(find_all("![[shot.png]](real.png)", true).is_empty());
+    }
+
+    #[test]
+    fn link_test() {
+        let doc = "[slides](./deck.pdf \"Deck\") [![badge](badge.png)](<my file.zip>) [ref][a] [both][b] ![both][b] <auto.pdf>\n\n[a]: a.pdf\n[b]: b.png\n";
+        assert_eq!(
+            find_images(doc)
+                .into_iter()
+                .map(|mth| mth.dest)
+                .collect::<Vec<_>>(),
+            ["badge.png", "b.png"]
+        );
+
+        let links = find_all(doc, false)
+            .into_iter()
+            .filter(|mth| mth.link.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links
+                .iter()
+                .map(|mth| mth.dest.as_str())
+                .collect::<Vec<_>>(),
+            ["./deck.pdf", "my file.zip", "a.pdf"]
+        );
+        assert_eq!(&doc[links[0].range.clone()], "./deck.pdf");
+        assert_eq!(
+            links[0].link,
+            Some(MatchedLink {
+                span: Some(0..27),
+                text: "slides".into()
+            })
+        );
+        assert_eq!(&doc[links[1].range.clone()], "<my file.zip>");
+        assert_eq!(links[1].link.as_ref().unwrap().text, "![badge](badge.png)");
+        assert_eq!(links[2].link.as_ref().unwrap().span, None);
+    }
+
+    #[test]
+    fn video_tag_test() {
+        let doc = "see [demo *run*](demo.mp4) and [ref][v]\n\n[v]: v.mp4\n";
+        let links = find_all(doc, false);
+        let (range, tag) = links[0].video_tag("https://x/a b.mp4?a=1&b=2").unwrap();
+        assert_eq!(
+            replace(doc, vec![(range, tag)]),
+            "see <video src=\"https://x/a%20b.mp4?a=1&amp;b=2\" controls>demo *run*</video> and [ref][v]\n\n[v]: v.mp4\n"
+        );
+        assert!(links[1].video_tag("https://x/v.mp4").is_none());
+
+        // the poster image is inside the link replaced by the tag
+        let doc = "[![poster](poster.png)](demo.mp4) end";
+        let mths = find_all(doc, false);
+        let replacements = mths
+            .iter()
+            .map(|mth| match mth.link {
+                Some(_) => mth.video_tag("https://x/demo.mp4").unwrap(),
+                None => (mth.range.clone(), "https://x/poster.png".to_string()),
+            })
+            .collect();
+        assert_eq!(
+            replace(doc, replacements),
+            "<video src=\"https://x/demo.mp4\" controls>![poster](poster.png)</video> end"
+        );
+    }
+
+    #[test]
+    fn byte_for_byte_test() {
+        let doc = "# t\r\n\r\n![a](a.png)\r\n\r\nno newline at end";
+        let mth = find_images(doc).pop().unwrap();
+        let new_doc = replace(doc, vec![(mth.range, "URL".into())]);
+        assert_eq!(new_doc, "# t\r\n\r\n![a](URL)\r\n\r\nno newline at end");
     }
 }
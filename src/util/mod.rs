@@ -1,10 +1,20 @@
 //! Utilities
 
-pub mod adjust_pwd;
+pub mod backend;
+pub mod data_uri;
+pub mod dest_type;
 pub mod echo;
 pub mod encode;
 pub mod file_type;
+pub mod file_url;
+pub mod gitlab;
 pub mod r#match;
 pub mod request;
 pub mod response;
 pub mod result;
+pub mod s3;
+#[cfg(test)]
+pub mod temp_dir;
+pub mod template;
+pub mod vault;
+pub mod walk;
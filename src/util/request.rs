@@ -1,80 +1,824 @@
-//! Sends HTTP PUT request
+//! The GitHub backend, which sends HTTP PUT requests to the GitHub contents
+//! API, and downloads the remote images that need to be rehosted.
+//!
+//! Files can also be uploaded in a single commit through the Git Data API,
+//! see [`Batch`].
+//!
+//! The Gitea backend, see [`Gitea`], shares the contents API requests.
 
-use crate::{config::UserConfig, util::encode::encode};
-use anyhow::Result;
+use crate::{
+    config::{Identity, UserConfig},
+    util::{
+        backend::{
+            commit_message, doc_name, fetch_json, send_json, upload_each, upload_path, Backend,
+            NewFile, UploadedFile,
+        },
+        encode::encode,
+        file_type::{file_type, hashed_file_name, FileType},
+        file_url::{encode_path, file_url, Location},
+        response::get_commit_sha,
+    },
+};
+use anyhow::{anyhow, Result};
+use percent_encoding::percent_decode_str;
+use rayon::prelude::*;
 use reqwest::{
-    blocking::{Client, Response},
-    header::{HeaderMap, HeaderValue},
+    blocking::{Client, RequestBuilder},
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    StatusCode, Url,
+};
+use serde::Serialize;
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    path::Path,
+    sync::{Mutex, OnceLock},
 };
-use std::path::Path;
 
-/// Picture uploader.
+/// The GitHub backend, which uploads the files to the Repo.
 #[derive(Debug)]
-pub struct Uploader {
+pub struct GitHub<'a> {
     client: Client,
     header: HeaderMap,
+    user_cfg: &'a UserConfig,
+    /// Default branch of the Repo, fetched when it is needed for the first time
+    default_branch: OnceLock<String>,
 }
 
-impl Uploader {
-    /// Initialize an [`Uploader`].
-    pub fn init(token: &str) -> Self {
-        let mut headers = HeaderMap::new();
-        headers.append("User-Agent", HeaderValue::from_static("pup"));
-        headers.append(
-            "accept",
-            HeaderValue::from_static("application/vnd.github.v3+json"),
-        );
-        // The GitHub API requires that the token header value should be
-        // "token TOKEN"
-        let token_with_prefix = format!("token {}", token);
-        headers.append(
-            "Authorization",
-            HeaderValue::from_str(token_with_prefix.as_str())
-                .expect("failed to parse header value"),
-        );
-
+impl<'a> GitHub<'a> {
+    /// Initialize a [`GitHub`] backend.
+    pub fn init(user_cfg: &'a UserConfig) -> Self {
         Self {
             client: Client::new(),
-            header: headers,
+            header: token_headers(user_cfg, "application/vnd.github.v3+json"),
+            user_cfg,
+            default_branch: OnceLock::new(),
         }
     }
 
-    /// Upload file specified in `path` to the Repo.
-    pub fn upload<P: AsRef<Path>>(&self, path: P, user_cfg: &UserConfig) -> Result<Response> {
-        let encoded_file_contents = encode(path.as_ref())?;
-        let file_name = path.as_ref().file_name().unwrap().to_str().unwrap();
+    /// Return true if the file at `url` (of the GitHub contents API) exists on
+    /// the configured branch and its contents are `contents`.
+    fn is_identical(&self, url: &str, contents: &[u8]) -> Result<bool> {
+        let existing = self.existing_file(url, self.user_cfg.branch.as_deref())?;
+        Ok(existing.is_some_and(|body| body["sha"].as_str() == Some(&git_blob_sha(contents))))
+    }
 
-        // init the json body
-        /*
-        {
-            "message": "upload",
-            "commiter": {
-                "name": "commiter-name",
-                "email": "commiter-email"
-            },
-            "content": "file-contents"
+    /// The configured branch, or the default branch of the Repo.
+    fn branch(&self) -> Result<String> {
+        if let Some(ref branch) = self.user_cfg.branch {
+            return Ok(branch.clone());
+        }
+        if let Some(branch) = self.default_branch.get() {
+            return Ok(branch.clone());
         }
-        */
-        let mut json_body= format!(
-            "{{\"message\": \"upload\", \"commiter\": {{\"name\": \"{}\", \"email\":\"{}\"}}, \"content\": \"",
-            user_cfg.github_user_name,
-            user_cfg.mail
-        ).into_bytes();
-        json_body.extend_from_slice(&encoded_file_contents);
-        json_body.extend_from_slice("\"}".as_bytes());
+
+        let repo = self.send(self.client.get(repo_api(self.user_cfg, "")))?;
+        let branch = json_str(&repo["default_branch"])?;
+        Ok(self.default_branch.get_or_init(|| branch).clone())
+    }
+
+    /// SHA of the head commit of `branch`.
+    fn head(&self, branch: &str) -> Result<String> {
+        let url = repo_api(self.user_cfg, &format!("git/ref/heads/{}", branch));
+        let head = self.send(self.client.get(url))?;
+        json_str(&head["object"]["sha"])
+    }
+
+    /// Fetch the file at `url` (of the GitHub contents API) on `branch`, return
+    /// `None` if it does not exist.
+    fn existing_file(&self, url: &str, branch: Option<&str>) -> Result<Option<Value>> {
+        fetch_file(self.client.get(url), &self.header, branch)
+    }
+
+    /// Send `req` with the GitHub headers, return the JSON response body.
+    ///
+    /// # Error
+    /// The request fails or the response status is not successful.
+    fn send(&self, req: RequestBuilder) -> Result<Value> {
+        send_json(req, &self.header)
+    }
+}
+
+impl Backend for GitHub<'_> {
+    /// Upload `contents` to the Repo as file `file_name` through the contents
+    /// API, the URL of the uploaded file is built by [`file_url`].
+    fn upload(&self, file_name: &str, contents: &[u8], doc: Option<&Path>) -> Result<UploadedFile> {
+        let user_cfg = self.user_cfg;
+        let file_name = upload_path(file_name, contents, doc, user_cfg)?;
+
+        let body = ContentsBody {
+            message: commit_message(&file_name, &doc_name(doc), user_cfg)?,
+            content: String::from_utf8(encode(contents)?)?,
+            sha: None,
+            branch: user_cfg.branch.as_deref(),
+            committer: user_cfg.committer.as_ref(),
+            author: user_cfg.author.as_ref(),
+        };
 
         // target URL
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/contents/{}",
-            user_cfg.github_user_name, user_cfg.github_repo_name, file_name
-        );
+        let url = contents_api(user_cfg, &file_name);
 
         let res = self
             .client
-            .put(url)
+            .put(url.as_str())
             .headers(self.header.clone())
-            .body(json_body)
+            .json(&body)
             .send()?;
-        Ok(res)
+
+        // an identical existing file is in the head of the branch
+        let sha = if matches!(
+            res.status(),
+            StatusCode::CONFLICT | StatusCode::UNPROCESSABLE_ENTITY
+        ) && self.is_identical(url.as_str(), contents)?
+        {
+            self.head(&self.branch()?)?
+        } else {
+            get_commit_sha(res)?
+        };
+
+        let location = Location {
+            branch: &self.branch()?,
+            path: &file_name,
+            sha: &sha,
+        };
+        Ok(UploadedFile {
+            url: file_url(user_cfg, location)?,
+            path: file_name,
+        })
+    }
+
+    /// Upload the files in a single [`Batch`] if `UserConfig.single_commit` is
+    /// set, one by one otherwise.
+    fn upload_all(&self, files: &[NewFile<'_>]) -> Vec<Result<UploadedFile>> {
+        if !self.user_cfg.single_commit {
+            return upload_each(self, files);
+        }
+
+        let batch = match Batch::new(self) {
+            Ok(batch) => batch,
+            Err(msg) => {
+                let msg = msg.to_string();
+                return files.iter().map(|_| Err(anyhow!(msg.clone()))).collect();
+            }
+        };
+        let added = files
+            .par_iter()
+            .map(|file| batch.add(&file.file_name, &file.contents, file.doc))
+            .collect::<Vec<_>>();
+        let committed = batch.commit().map_err(|msg| msg.to_string());
+
+        added
+            .into_iter()
+            .map(|path| {
+                let path = path?;
+                let sha = committed.as_ref().map_err(|msg| anyhow!(msg.clone()))?;
+                Ok(UploadedFile {
+                    url: batch.url(&path, sha)?,
+                    path,
+                })
+            })
+            .collect()
+    }
+
+    /// Return true if the file at `path` exists on the branch.
+    fn exists(&self, path: &str) -> Result<bool> {
+        let url = contents_api(self.user_cfg, path);
+        Ok(self.existing_file(&url, Some(&self.branch()?))?.is_some())
+    }
+
+    /// Delete the file at `path` from the branch in a new commit.
+    fn delete(&self, path: &str) -> Result<()> {
+        let user_cfg = self.user_cfg;
+        let url = contents_api(user_cfg, path);
+        let branch = self.branch()?;
+        let existing = self
+            .existing_file(&url, Some(&branch))?
+            .ok_or_else(|| anyhow!("{} does not exist", path))?;
+
+        let body = ContentsBody {
+            message: format!("delete {}", path),
+            content: String::new(),
+            sha: Some(json_str(&existing["sha"])?),
+            branch: Some(&branch),
+            committer: user_cfg.committer.as_ref(),
+            author: user_cfg.author.as_ref(),
+        };
+        self.send(self.client.delete(url).json(&body))?;
+
+        Ok(())
+    }
+}
+
+/// Files uploaded in a single commit through the Git Data API.
+///
+/// Files are added by [`Batch::add`], possibly in parallel, as blobs, then a
+/// tree and a commit containing all of them are created by [`Batch::commit`].
+#[derive(Debug)]
+pub struct Batch<'a> {
+    github: &'a GitHub<'a>,
+    /// Branch to commit to
+    branch: String,
+    /// SHAs of the added files, keyed by their paths
+    entries: Mutex<BTreeMap<String, String>>,
+    /// File names of the documents that reference the added files
+    docs: Mutex<BTreeSet<String>>,
+}
+
+impl<'a> Batch<'a> {
+    /// Start a batch on the configured branch, or the default branch of the
+    /// Repo.
+    pub fn new(github: &'a GitHub<'a>) -> Result<Self> {
+        Ok(Self {
+            github,
+            branch: github.branch()?,
+            entries: Mutex::new(BTreeMap::new()),
+            docs: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// Add `contents` as file `file_name` to the batch, return its path in the
+    /// Repo, see [`GitHub::upload`](Backend::upload).
+    ///
+    /// If a file with the same contents exists there, or has been added to the
+    /// batch, it is not added again.
+    ///
+    /// # Error
+    /// A different file exists there or has been added to the batch, or the
+    /// blob can not be created.
+    pub fn add(&self, file_name: &str, contents: &[u8], doc: Option<&Path>) -> Result<String> {
+        let user_cfg = self.github.user_cfg;
+        let path = upload_path(file_name, contents, doc, user_cfg)?;
+
+        let sha = git_blob_sha(contents);
+        if self.entries.lock().unwrap().contains_key(&path) {
+            self.insert(&path, &sha, doc)?;
+            return Ok(path);
+        }
+
+        let url = contents_api(user_cfg, &path);
+        if let Some(existing) = self.github.existing_file(&url, Some(&self.branch))? {
+            if existing["sha"].as_str() == Some(sha.as_str()) {
+                return Ok(path);
+            }
+            return Err(anyhow!("a different file exists at {}", path));
+        }
+
+        let body = BlobBody {
+            content: String::from_utf8(encode(contents)?)?,
+            encoding: "base64",
+        };
+        let blob = self.github.send(
+            self.github
+                .client
+                .post(repo_api(user_cfg, "git/blobs"))
+                .json(&body),
+        )?;
+        if blob["sha"].as_str() != Some(sha.as_str()) {
+            return Err(anyhow!("unexpected blob SHA of {}", path));
+        }
+
+        // another file may have been added to the same path in the meantime
+        self.insert(&path, &sha, doc)?;
+
+        Ok(path)
+    }
+
+    /// Record the file at `path` whose blob SHA is `sha` in the batch, and the
+    /// document that references it.
+    ///
+    /// # Error
+    /// A file with a different SHA has been added to `path`.
+    fn insert(&self, path: &str, sha: &str, doc: Option<&Path>) -> Result<()> {
+        match self.entries.lock().unwrap().entry(path.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(sha.to_string());
+            }
+            Entry::Occupied(entry) if entry.get() == sha => (),
+            Entry::Occupied(_) => {
+                return Err(anyhow!("a different file has been added to {}", path));
+            }
+        }
+        if let Some(doc) = doc {
+            self.docs.lock().unwrap().insert(doc_name(Some(doc)));
+        }
+
+        Ok(())
+    }
+
+    /// Commit all the added files to the branch, return the SHA of the commit,
+    /// or the head of the branch if no file is added.
+    pub fn commit(&self) -> Result<String> {
+        let user_cfg = self.github.user_cfg;
+        let head = self.github.head(&self.branch)?;
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap())
+            .into_iter()
+            .map(|(path, sha)| TreeEntry {
+                path,
+                mode: "100644",
+                kind: "blob",
+                sha,
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return Ok(head);
+        }
+
+        let client = &self.github.client;
+        let head_commit = self
+            .github
+            .send(client.get(repo_api(user_cfg, &format!("git/commits/{}", head))))?;
+
+        let files = entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let docs = self
+            .docs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|doc| doc.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let tree_body = TreeBody {
+            base_tree: json_str(&head_commit["tree"]["sha"])?,
+            tree: entries,
+        };
+        let tree = self.github.send(
+            client
+                .post(repo_api(user_cfg, "git/trees"))
+                .json(&tree_body),
+        )?;
+
+        let commit_body = CommitBody {
+            message: commit_message(&files, &docs, user_cfg)?,
+            tree: json_str(&tree["sha"])?,
+            parents: vec![head],
+            committer: user_cfg.committer.as_ref(),
+            author: user_cfg.author.as_ref(),
+        };
+        let commit = self.github.send(
+            client
+                .post(repo_api(user_cfg, "git/commits"))
+                .json(&commit_body),
+        )?;
+
+        // fails if the branch has been updated since we read it
+        let sha = json_str(&commit["sha"])?;
+        let ref_url = repo_api(user_cfg, &format!("git/refs/heads/{}", self.branch));
+        let ref_body = RefBody {
+            sha: sha.clone(),
+            force: false,
+        };
+        self.github.send(client.patch(ref_url).json(&ref_body))?;
+
+        Ok(sha)
+    }
+
+    /// URL of the file at `path` in commit `sha` of the branch, see
+    /// [`file_url`].
+    pub fn url(&self, path: &str, sha: &str) -> Result<String> {
+        let location = Location {
+            branch: &self.branch,
+            path,
+            sha,
+        };
+        file_url(self.github.user_cfg, location)
+    }
+}
+
+/// The Gitea backend, which uploads the files to the Repo on a Gitea or
+/// Forgejo instance through its contents API.
+///
+/// The API is close to the GitHub contents API, but it is under
+/// `{gitea_url}/api/v1`, and the URL of the uploaded file is taken from the
+/// response, see [`download_url`].
+#[derive(Debug)]
+pub struct Gitea<'a> {
+    client: Client,
+    header: HeaderMap,
+    user_cfg: &'a UserConfig,
+}
+
+impl<'a> Gitea<'a> {
+    /// Initialize a [`Gitea`] backend.
+    pub fn init(user_cfg: &'a UserConfig) -> Self {
+        Self {
+            client: Client::new(),
+            header: token_headers(user_cfg, "application/json"),
+            user_cfg,
+        }
+    }
+
+    /// Fetch the file at `path` on the configured branch, return `None` if it
+    /// does not exist.
+    fn existing_file(&self, path: &str) -> Result<Option<Value>> {
+        let url = gitea_contents_api(self.user_cfg, path);
+        fetch_file(
+            self.client.get(url),
+            &self.header,
+            self.user_cfg.branch.as_deref(),
+        )
+    }
+}
+
+impl Backend for Gitea<'_> {
+    /// Upload `contents` to the Repo as file `file_name`, return the
+    /// `download_url` of the uploaded file.
+    ///
+    /// An existing file is compared by its blob SHA, like on GitHub.
+    fn upload(&self, file_name: &str, contents: &[u8], doc: Option<&Path>) -> Result<UploadedFile> {
+        let user_cfg = self.user_cfg;
+        let path = upload_path(file_name, contents, doc, user_cfg)?;
+
+        let body = ContentsBody {
+            message: commit_message(&path, &doc_name(doc), user_cfg)?,
+            content: String::from_utf8(encode(contents)?)?,
+            sha: None,
+            branch: user_cfg.branch.as_deref(),
+            committer: user_cfg.committer.as_ref(),
+            author: user_cfg.author.as_ref(),
+        };
+        let url = gitea_contents_api(user_cfg, &path);
+        let created = send_json(self.client.post(url).json(&body), &self.header);
+
+        let url = match created {
+            Ok(res) => download_url(&res["content"])?,
+            Err(msg) => match self.existing_file(&path)? {
+                Some(existing) if existing["sha"].as_str() == Some(&git_blob_sha(contents)) => {
+                    download_url(&existing)?
+                }
+                _ => return Err(msg),
+            },
+        };
+
+        Ok(UploadedFile { path, url })
+    }
+
+    /// Return true if the file at `path` exists on the branch.
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.existing_file(path)?.is_some())
+    }
+
+    /// Delete the file at `path` from the branch in a new commit.
+    fn delete(&self, path: &str) -> Result<()> {
+        let user_cfg = self.user_cfg;
+        let existing = self
+            .existing_file(path)?
+            .ok_or_else(|| anyhow!("{} does not exist", path))?;
+
+        let body = ContentsBody {
+            message: format!("delete {}", path),
+            content: String::new(),
+            sha: Some(json_str(&existing["sha"])?),
+            branch: user_cfg.branch.as_deref(),
+            committer: user_cfg.committer.as_ref(),
+            author: user_cfg.author.as_ref(),
+        };
+        let url = gitea_contents_api(user_cfg, path);
+        send_json(self.client.delete(url).json(&body), &self.header)?;
+
+        Ok(())
+    }
+}
+
+/// Headers of the GitHub and Gitea API requests, `accept` is the media type
+/// of the responses.
+///
+/// Both APIs take the token header value "token TOKEN".
+fn token_headers(user_cfg: &UserConfig, accept: &'static str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append("User-Agent", HeaderValue::from_static("pup"));
+    headers.append("accept", HeaderValue::from_static(accept));
+    let token_with_prefix = format!("token {}", user_cfg.token.as_ref().unwrap());
+    headers.append(
+        "Authorization",
+        HeaderValue::from_str(token_with_prefix.as_str()).expect("failed to parse header value"),
+    );
+
+    headers
+}
+
+/// URL of the file at `path` in the Gitea contents API, i.e.,
+/// `{gitea_url}/api/v1/repos/{owner}/{repo}/contents/{path}`.
+fn gitea_contents_api(user_cfg: &UserConfig, path: &str) -> String {
+    format!(
+        "{}/api/v1/repos/{}/{}/contents/{}",
+        user_cfg.gitea_url.trim_end_matches('/'),
+        user_cfg.github_user_name,
+        user_cfg.github_repo_name,
+        encode_path(path)
+    )
+}
+
+/// `download_url` of a file of the Gitea contents API, i.e.,
+/// `{gitea_url}/{owner}/{repo}/raw/branch/{branch}/{path}`.
+fn download_url(content: &Value) -> Result<String> {
+    json_str(&content["download_url"])
+}
+
+/// Send `req` with `header`, return the JSON response body of the file on
+/// `branch`, or `None` if the file does not exist, see [`fetch_json`].
+fn fetch_file(
+    req: RequestBuilder,
+    header: &HeaderMap,
+    branch: Option<&str>,
+) -> Result<Option<Value>> {
+    let req = match branch {
+        Some(branch) => req.query(&[("ref", branch)]),
+        None => req,
+    };
+    fetch_json(req, header)
+}
+
+/// Request body of the Git Data API to create a blob
+#[derive(Debug, Serialize)]
+struct BlobBody {
+    /// File contents
+    content: String,
+    /// Encoding of `content`
+    encoding: &'static str,
+}
+
+/// Entry of a tree of the Git Data API
+#[derive(Debug, Serialize)]
+struct TreeEntry {
+    /// Path of the file in the Repo
+    path: String,
+    /// File mode
+    mode: &'static str,
+    /// Object type
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// SHA-1 of the object
+    sha: String,
+}
+
+/// Request body of the Git Data API to create a tree
+#[derive(Debug, Serialize)]
+struct TreeBody {
+    /// SHA-1 of the tree that the entries are added to
+    base_tree: String,
+    /// Entries to add
+    tree: Vec<TreeEntry>,
+}
+
+/// Request body of the Git Data API to create a commit
+#[derive(Debug, Serialize)]
+struct CommitBody<'a> {
+    /// Commit message
+    message: String,
+    /// SHA-1 of the tree
+    tree: String,
+    /// SHA-1 of the parent commits
+    parents: Vec<String>,
+    /// Committer of the commit, the authenticated user if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    committer: Option<&'a Identity>,
+    /// Author of the commit, the committer if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a Identity>,
+}
+
+/// Request body of the Git Data API to update a reference
+#[derive(Debug, Serialize)]
+struct RefBody {
+    /// SHA-1 of the commit
+    sha: String,
+    /// Update even if it is not a fast-forward
+    force: bool,
+}
+
+/// URL of the GitHub API endpoint `path` of the Repo, i.e.,
+/// `{api_base_url}/repos/{owner}/{repo}/{path}`.
+fn repo_api(user_cfg: &UserConfig, path: &str) -> String {
+    let url = format!(
+        "{}/repos/{}/{}/{}",
+        user_cfg.api_base_url.trim_end_matches('/'),
+        user_cfg.github_user_name,
+        user_cfg.github_repo_name,
+        path
+    );
+    url.trim_end_matches('/').to_string()
+}
+
+/// URL of the file at `path` in the GitHub contents API, every segment of the
+/// path is URL-encoded, see [`encode_path`].
+fn contents_api(user_cfg: &UserConfig, path: &str) -> String {
+    repo_api(user_cfg, &format!("contents/{}", encode_path(path)))
+}
+
+/// Return the string `value` of a JSON response.
+fn json_str(value: &Value) -> Result<String> {
+    value
+        .as_str()
+        .map(|value| value.to_string())
+        .ok_or_else(|| anyhow!("unexpected response from the server"))
+}
+
+/// Request body of the GitHub contents API to create or delete a file
+#[derive(Debug, Serialize)]
+struct ContentsBody<'a> {
+    /// Commit message
+    message: String,
+    /// Base64-encoded file contents, unused by deletions
+    #[serde(skip_serializing_if = "String::is_empty")]
+    content: String,
+    /// Blob SHA of the file to delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha: Option<String>,
+    /// Branch to commit to, the default branch if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<&'a str>,
+    /// Committer of the commit, the authenticated user if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    committer: Option<&'a Identity>,
+    /// Author of the commit, the committer if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a Identity>,
+}
+
+/// The SHA-1 of `contents` as a git blob, which is the `sha` of a file in the
+/// GitHub contents API.
+fn git_blob_sha(contents: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()));
+    hasher.update(contents);
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Remote image downloader.
+///
+/// Unlike the backends, the TOKEN is NOT sent.
+#[derive(Debug)]
+pub struct Downloader {
+    client: Client,
+}
+
+/// A downloaded remote image.
+#[derive(Debug)]
+pub struct RemoteImage {
+    /// The file name taken from the URL if it is an image file name, or picked
+    /// from the `Content-Type` and the hash of the image otherwise.
+    pub file_name: String,
+    /// Image contents
+    pub contents: Vec<u8>,
+}
+
+impl Downloader {
+    /// Initialize a [`Downloader`].
+    pub fn init() -> Self {
+        Self {
+            client: Client::new(),
+        }
+    }
+
+    /// Download the remote image at `url`.
+    pub fn download(&self, url: &Url) -> Result<RemoteImage> {
+        let res = self
+            .client
+            .get(url.as_str())
+            .header("User-Agent", "pup")
+            .send()?
+            .error_for_status()?;
+        let mime = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        let contents = res.bytes()?.to_vec();
+
+        let file_name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+            .filter(|segment| file_type(segment) == FileType::Image);
+        let file_name = match file_name {
+            Some(file_name) => file_name,
+            None => hashed_file_name(&contents, &mime)?,
+        };
+
+        Ok(RemoteImage {
+            file_name,
+            contents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::test_config;
+    use crate::config::BackendKind;
+    use serde_json::from_str;
+
+    #[test]
+    fn test_git_blob_sha() {
+        // `echo hello | git hash-object --stdin`
+        assert_eq!(
+            git_blob_sha(b"hello\n"),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+        assert_eq!(
+            git_blob_sha(b""),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+    }
+
+    #[test]
+    fn test_contents_body() {
+        let cfg =
+            test_config("branch = \"assets\"\ncommitter = { name = 'A \"B\"', email = \"a@b.c\" }");
+        let body = ContentsBody {
+            message: "upload".to_string(),
+            content: "aGk=".to_string(),
+            sha: None,
+            branch: cfg.branch.as_deref(),
+            committer: cfg.committer.as_ref(),
+            author: cfg.author.as_ref(),
+        };
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"message":"upload","content":"aGk=","branch":"assets","committer":{"name":"A \"B\"","email":"a@b.c"}}"#
+        );
+    }
+
+    #[test]
+    fn test_contents_api() {
+        assert_eq!(
+            contents_api(&test_config(""), "2024/Issue #12.png"),
+            "https://api.github.com/repos/user/repo/contents/2024/Issue%20%2312.png"
+        );
+    }
+
+    #[test]
+    fn test_gitea() {
+        let cfg = test_config("backend = \"forgejo\"\ngitea_url = \"http://localhost:3000/\"");
+        assert_eq!(cfg.backend, BackendKind::Gitea);
+        assert_eq!(
+            gitea_contents_api(&cfg, "2024/a b.png"),
+            "http://localhost:3000/api/v1/repos/user/repo/contents/2024/a%20b.png"
+        );
+
+        // response of creating a file, trimmed
+        let res: Value = from_str(
+            r#"{
+                "content": {
+                    "path": "2024/a b.png",
+                    "sha": "ce013625030ba8dba906f756967f9e9ca394464a",
+                    "download_url": "http://localhost:3000/user/repo/raw/branch/main/2024/a%20b.png"
+                },
+                "commit": { "sha": "0123abcd" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            download_url(&res["content"]).unwrap(),
+            "http://localhost:3000/user/repo/raw/branch/main/2024/a%20b.png"
+        );
+        assert!(download_url(&res["commit"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_url() {
+        let mut cfg = test_config("branch = \"img/assets\"");
+        cfg.token = Some("token".to_string());
+        let github = GitHub::init(&cfg);
+        let batch = Batch::new(&github).unwrap();
+        assert_eq!(
+            batch.url("2024/a b.png", "0123abcd").unwrap(),
+            "https://github.com/user/repo/blob/img/assets/2024/a%20b.png"
+        );
+    }
+
+    #[test]
+    fn test_batch_insert() {
+        let mut cfg = test_config("branch = \"main\"");
+        cfg.token = Some("token".to_string());
+        let github = GitHub::init(&cfg);
+        let batch = Batch::new(&github).unwrap();
+
+        batch
+            .insert("a.png", "aaaa", Some(Path::new("x.md")))
+            .unwrap();
+        batch
+            .insert("a.png", "aaaa", Some(Path::new("y.md")))
+            .unwrap();
+        assert!(batch
+            .insert("a.png", "bbbb", Some(Path::new("z.md")))
+            .is_err());
+        batch.insert("b.png", "bbbb", None).unwrap();
+
+        assert_eq!(batch.entries.lock().unwrap().len(), 2);
+        assert_eq!(batch.entries.lock().unwrap()["a.png"], "aaaa");
+        assert_eq!(
+            *batch.docs.lock().unwrap(),
+            BTreeSet::from(["x.md".to_string(), "y.md".to_string()])
+        );
     }
 }
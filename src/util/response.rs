@@ -11,23 +11,23 @@ pub enum FailedCases {
     Conflict,
     #[error("422: ValidationFailed")]
     ValidationFailed,
-    #[error("The picture is uploaded but no URL is returned.")]
-    CreatedButUrlNotFound,
+    #[error("The picture is uploaded but no commit SHA is returned.")]
+    CreatedButShaNotFound,
     #[error("This error is not covered by pup")]
     NotCoveredCase,
 }
 
-/// Parse URL from the returned json body
-pub fn get_url(body: Response) -> Result<String, FailedCases> {
+/// Parse the SHA of the created commit from the returned json body
+pub fn get_commit_sha(body: Response) -> Result<String, FailedCases> {
     match body.status() {
         // returns 201
         StatusCode::CREATED => {
             let body: String = body.text().expect("can not get response body");
             let val: Value = from_str(&body).expect("can not parse response body");
-            if let Value::String(ref url) = val["content"]["html_url"] {
-                Ok(url.clone())
+            if let Value::String(ref sha) = val["commit"]["sha"] {
+                Ok(sha.clone())
             } else {
-                Err(FailedCases::CreatedButUrlNotFound)
+                Err(FailedCases::CreatedButShaNotFound)
             }
         }
         // returns 409
@@ -12,18 +12,60 @@ use std::{
 pub struct MdManipulationResult {
     total: usize,
     failed: usize,
+    /// Report to stderr instead of stdout.
+    stderr: bool,
 }
 
 impl MdManipulationResult {
+    /// Create a result that reports to stderr, used when stdout is occupied by
+    /// the manipulated document.
+    pub fn stderr() -> Self {
+        Self {
+            stderr: true,
+            ..Self::default()
+        }
+    }
+
+    /// Print the statistical result, to stderr if [`Self::stderr`] is used.
+    pub fn report(&self) {
+        if self.stderr {
+            eprintln!("{}", self);
+        } else {
+            println!("{}", self);
+        }
+    }
+
     /// Check out the result of our image manipulation and report it to the user.
-    pub fn res_handling(&mut self, res: Result<(), Box<dyn Error>>, image_path: &Path) {
+    ///
+    /// Every occurrence of an image should be checked out separately.
+    ///
+    /// Return the value of `res` if it succeeded.
+    pub fn res_handling<T: Clone>(
+        &mut self,
+        res: &Result<T, Box<dyn Error>>,
+        image_path: &Path,
+    ) -> Option<T> {
         self.total += 1;
-        if let Err(msg) = res {
-            println!("find: {:?}\n[{}]: {:?}", image_path, "FAILED".red(), msg);
-            self.failed += 1;
+        let (message, val) = match res {
+            Ok(val) => (
+                format!("find: {:?}\n[{}]", image_path, "DONE".green()),
+                Some(val.clone()),
+            ),
+            Err(msg) => {
+                self.failed += 1;
+                (
+                    format!("find: {:?}\n[{}]: {:?}", image_path, "FAILED".red(), msg),
+                    None,
+                )
+            }
+        };
+        if self.stderr {
+            eprintln!("{}", message);
         } else {
-            println!("find: {:?}\n[{}]", image_path, "DONE".green());
+            println!("{}", message);
         }
+
+        val
     }
 }
 
@@ -43,3 +85,25 @@ impl Display for MdManipulationResult {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_res_handling() {
+        // the filter mode writes the document to stdout
+        let mut res = MdManipulationResult::stderr();
+        assert!(res.stderr);
+        assert!(!MdManipulationResult::default().stderr);
+
+        let done: Result<String, Box<dyn Error>> = Ok("https://x/a.png".to_string());
+        let failed: Result<String, Box<dyn Error>> = Err("failed".into());
+        assert_eq!(
+            res.res_handling(&done, Path::new("a.png")).as_deref(),
+            Some("https://x/a.png")
+        );
+        assert_eq!(res.res_handling(&failed, Path::new("b.png")), None);
+        assert_eq!((res.total, res.failed), (2, 1));
+    }
+}
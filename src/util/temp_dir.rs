@@ -0,0 +1,46 @@
+//! Temporary directories for the tests, removed when they are dropped, even if
+//! the test fails.
+
+use std::{
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all, write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A temporary directory, removed with everything in it when dropped
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory `pup-{name}-{pid}` in the temporary directory
+    /// of the system, `name` should be unique among the tests.
+    pub fn new(name: &str) -> Self {
+        let path = temp_dir().join(format!("pup-{}-{}", name, process::id()));
+        let _ = remove_dir_all(&path);
+        create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Path of this directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `contents` to the file at `file`, relative to this directory, the
+    /// parent directories are created. Return the path of the file.
+    pub fn write(&self, file: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,107 @@
+//! Expand the templates in the configuration file, e.g., `{yyyy}/{mm}/{name}`.
+
+use anyhow::{anyhow, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Replace every `{variable}` in `template` with `lookup(variable)`, `{{` and
+/// `}}` are literal braces.
+///
+/// # Error
+/// A variable is unknown, i.e., `lookup` returns `None`, or a brace is not
+/// closed.
+pub fn expand<F>(template: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(idx) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..idx]);
+        let brace = rest.as_bytes()[idx];
+        rest = &rest[idx + 1..];
+
+        if rest.as_bytes().first() == Some(&brace) {
+            expanded.push(brace as char);
+            rest = &rest[1..];
+            continue;
+        }
+        if brace == b'}' {
+            return Err(anyhow!("unmatched `}}` in template `{}`", template));
+        }
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed `{{` in template `{}`", template))?;
+        let variable = &rest[..end];
+        let value = lookup(variable).ok_or_else(|| {
+            anyhow!(
+                "unknown variable `{{{}}}` in template `{}`",
+                variable,
+                template
+            )
+        })?;
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Today's date in UTC, i.e., (year, month, day).
+pub fn today() -> (i64, u32, u32) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    civil_date(secs as i64 / 86400)
+}
+
+/// Convert the days since 1970-01-01 to (year, month, day).
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(variable: &str) -> Option<String> {
+        match variable {
+            "yyyy" => Some("2024".to_string()),
+            "name" => Some("a.png".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("{yyyy}/{name}", lookup).unwrap(), "2024/a.png");
+        assert_eq!(expand("{{name}}-{name}", lookup).unwrap(), "{name}-a.png");
+        assert_eq!(expand("plain", lookup).unwrap(), "plain");
+        assert!(expand("{unknown}", lookup).is_err());
+        assert!(expand("{name", lookup).is_err());
+        assert!(expand("name}", lookup).is_err());
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(19783), (2024, 3, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+    }
+}
@@ -0,0 +1,187 @@
+//! Obsidian vault support, used to find the files embedded by the wiki embeds,
+//! i.e., `![[screenshot.png]]`.
+//!
+//! Obsidian does not resolve these files relative to the note, they are
+//! looked up in the attachment folder and then in the whole vault.
+
+use serde::Deserialize;
+use std::{
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// The part of `.obsidian/app.json` used by `pup`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppConfig {
+    #[serde(default)]
+    attachment_folder_path: String,
+}
+
+/// Type to represent an Obsidian vault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vault {
+    /// Root directory of the vault, which contains the `.obsidian` directory.
+    root: PathBuf,
+    /// `attachmentFolderPath` of the vault configuration, `/` means the vault
+    /// root, and a path starting with `./` is relative to the note.
+    attachment_folder: String,
+}
+
+impl Vault {
+    /// Find the vault that `note_dir` belongs to, i.e., the closest ancestor
+    /// directory that contains a `.obsidian` directory.
+    pub fn discover(note_dir: &Path) -> Option<Self> {
+        let root = note_dir
+            .ancestors()
+            .find(|dir| dir.join(".obsidian").is_dir())?
+            .to_path_buf();
+        let app_config = read_to_string(root.join(".obsidian").join("app.json"))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<AppConfig>(&contents).ok())
+            .unwrap_or_default();
+
+        Some(Self {
+            root,
+            attachment_folder: app_config.attachment_folder_path,
+        })
+    }
+
+    /// The attachment folder for the notes in `note_dir`.
+    fn attachment_dir(&self, note_dir: &Path) -> PathBuf {
+        let folder = self.attachment_folder.as_str();
+        if folder == "." || folder.starts_with("./") {
+            note_dir.join(folder)
+        } else {
+            self.root.join(folder.trim_start_matches('/'))
+        }
+    }
+
+    /// Find the file embedded by `![[target]]` in a note in `note_dir`.
+    ///
+    /// The attachment folder, the note directory and the vault root are tried
+    /// in order, then the whole vault is searched, the file closest to the
+    /// vault root wins.
+    pub fn resolve(&self, note_dir: &Path, target: &Path) -> Option<PathBuf> {
+        let candidates = [
+            self.attachment_dir(note_dir).join(target),
+            note_dir.join(target),
+            self.root.join(target),
+        ];
+        if let Some(path) = candidates.into_iter().find(|path| path.is_file()) {
+            return Some(path);
+        }
+
+        let mut found = Vec::new();
+        search(self.root.as_path(), target, &mut found);
+        found.sort_by_key(|path| (path.components().count(), path.clone()));
+        found.into_iter().next()
+    }
+}
+
+/// Recursively search `dir` for the files whose path ends with `target`,
+/// hidden entries are skipped.
+fn search(dir: &Path, target: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => search(path.as_path(), target, found),
+            Ok(file_type) if file_type.is_file() && path.ends_with(target) => found.push(path),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    /// Create a vault with the given `app.json` and files in a temporary
+    /// directory.
+    fn vault(name: &str, app_json: &str, files: &[&str]) -> TempDir {
+        let dir = TempDir::new(&format!("vault-{}", name));
+        dir.write(".obsidian/app.json", app_json);
+        for file in files {
+            dir.write(file, "");
+        }
+        dir
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = vault("discover", "{}", &["notes/deep/note.md"]);
+        let root = dir.path();
+        let vault = Vault::discover(&root.join("notes/deep")).unwrap();
+        assert_eq!(vault.root, root);
+        assert_eq!(vault.attachment_folder, "");
+
+        assert!(Vault::discover(Path::new("/")).is_none());
+    }
+
+    #[test]
+    fn test_attachment_folder() {
+        let dir = vault(
+            "attachment",
+            r#"{"attachmentFolderPath": "attachments"}"#,
+            &["attachments/a.png", "notes/a.png", "notes/b.png"],
+        );
+        let root = dir.path();
+        let vault = Vault::discover(root).unwrap();
+        let note_dir = root.join("notes");
+
+        assert_eq!(
+            vault.resolve(&note_dir, Path::new("a.png")),
+            Some(root.join("attachments/a.png"))
+        );
+        assert_eq!(
+            vault.resolve(&note_dir, Path::new("b.png")),
+            Some(root.join("notes/b.png"))
+        );
+    }
+
+    #[test]
+    fn test_relative_attachment_folder() {
+        let dir = vault(
+            "relative",
+            r#"{"attachmentFolderPath": "./assets"}"#,
+            &["notes/assets/a.png", "assets/a.png"],
+        );
+        let root = dir.path();
+        let vault = Vault::discover(root).unwrap();
+
+        assert_eq!(
+            vault.resolve(&root.join("notes"), Path::new("a.png")),
+            Some(root.join("notes/./assets/a.png"))
+        );
+    }
+
+    #[test]
+    fn test_vault_wide_lookup() {
+        let dir = vault(
+            "lookup",
+            "{}",
+            &["x/y/z/a.png", "x/y/a.png", ".trash/b.png", "p/img/c.png"],
+        );
+        let root = dir.path();
+        let vault = Vault::discover(root).unwrap();
+        let note_dir = root.join("notes");
+
+        assert_eq!(
+            vault.resolve(&note_dir, Path::new("a.png")),
+            Some(root.join("x/y/a.png"))
+        );
+        assert_eq!(vault.resolve(&note_dir, Path::new("b.png")), None);
+        assert_eq!(
+            vault.resolve(&note_dir, Path::new("img/c.png")),
+            Some(root.join("p/img/c.png"))
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Find the markdown files in a directory tree.
+//!
+//! Files ignored by `.gitignore` (and `.ignore`) and hidden files are skipped.
+
+use crate::util::file_type::{file_type, FileType};
+use anyhow::Result;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use std::path::{Path, PathBuf};
+
+/// Return the markdown files under `dir` recursively, sorted by path.
+///
+/// `include` and `exclude` are globs matched against the paths relative to
+/// `dir`, if `include` is not empty, only the files matched by it are
+/// returned. Files matched by `exclude` are never returned.
+pub fn markdown_files(dir: &Path, include: &[String], exclude: &[String]) -> Result<Vec<PathBuf>> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in include {
+        overrides.add(glob)?;
+    }
+    for glob in exclude {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let mut files = Vec::new();
+    let walker = WalkBuilder::new(dir)
+        .overrides(overrides.build()?)
+        // `.gitignore` is respected even if `dir` is not in a git repo
+        .require_git(false)
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        let is_file = entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file());
+        if is_file && file_type(entry.path()) == FileType::Markdown {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp_dir::TempDir;
+
+    #[test]
+    fn markdown_files_test() {
+        let temp = TempDir::new("walk");
+        let dir = temp.path();
+        for file in [
+            "a.md",
+            "b.png",
+            "docs/c.markdown",
+            "docs/draft/d.md",
+            "build/e.md",
+            ".hidden/f.md",
+        ] {
+            temp.write(file, "");
+        }
+        temp.write(".gitignore", "build/\n");
+
+        let relative = |files: Vec<PathBuf>| {
+            files
+                .iter()
+                .map(|file| {
+                    file.strip_prefix(dir)
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            relative(markdown_files(dir, &[], &[]).unwrap()),
+            ["a.md", "docs/c.markdown", "docs/draft/d.md"]
+        );
+        assert_eq!(
+            relative(
+                markdown_files(dir, &["docs/**".to_string()], &["draft".to_string()]).unwrap()
+            ),
+            ["docs/c.markdown"]
+        );
+    }
+}